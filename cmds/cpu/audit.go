@@ -0,0 +1,134 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"encoding/json"
+	"flag"
+	"log"
+	"os"
+	"strings"
+	"sync"
+	"time"
+
+	"github.com/gliderlabs/ssh"
+)
+
+var auditLog = flag.String("auditlog", "", "file the server appends JSON records of the start and end of each session to (default none)")
+
+// audit holds the open -auditlog; f is nil if there is none.
+var audit struct {
+	sync.Mutex
+	f *os.File
+}
+
+// openAudit opens the audit log for appending, creating it if
+// need be. Only we get to read it.
+func openAudit(file string) error {
+	f, err := os.OpenFile(file, os.O_WRONLY|os.O_APPEND|os.O_CREATE, 0600)
+	if err != nil {
+		return err
+	}
+	audit.f = f
+	return nil
+}
+
+// auditRecord is the record of one session. A session gets a
+// "start" record when its command starts, and an "end" record,
+// with End and Status, when it is over. Sessions refused before
+// their command runs get only the end record. The status stays 1
+// unless the command runs and exits.
+type auditRecord struct {
+	Event    string      `json:"event"`
+	User     string      `json:"user"`
+	Identity interface{} `json:"identity"`
+	Remote   string      `json:"remote"`
+	Argv     []string    `json:"argv"`
+	Cwd      string      `json:"cwd"`
+	Pid      int         `json:"pid,omitempty"`
+	Start    time.Time   `json:"start"`
+	End      *time.Time  `json:"end,omitempty"`
+	Status   *int        `json:"status,omitempty"`
+	status   int
+}
+
+func newAuditRecord(s ssh.Session) *auditRecord {
+	return &auditRecord{
+		User:     s.User(),
+		Identity: s.Context().Value(identityKey),
+		Remote:   s.RemoteAddr().String(),
+		Argv:     s.Command(),
+		Cwd:      sessionDir(s.Command(), s.Environ()),
+		Start:    time.Now(),
+		status:   1,
+	}
+}
+
+// started writes the start record, now that the command runs as pid.
+func (r *auditRecord) started(pid int) {
+	r.Pid = pid
+	r.write("start")
+}
+
+// ended writes the end record.
+func (r *auditRecord) ended() {
+	end := time.Now()
+	r.End, r.Status = &end, &r.status
+	r.write("end")
+}
+
+// write appends r to the audit log as event. Each record is
+// synced, so a crash loses none of them.
+func (r *auditRecord) write(event string) {
+	audit.Lock()
+	defer audit.Unlock()
+	if audit.f == nil {
+		return
+	}
+	r.Event = event
+	b, err := json.Marshal(r)
+	if err != nil {
+		log.Printf("Warning: audit: %v", err)
+		return
+	}
+	if _, err := audit.f.Write(append(b, '\n')); err != nil {
+		log.Printf("Warning: audit: %v", err)
+		return
+	}
+	if err := audit.f.Sync(); err != nil {
+		log.Printf("Warning: audit: %v", err)
+	}
+}
+
+// argValue returns the value a cpu client gives the flag name of
+// its remote in a, or "" if it gives none.
+func argValue(a []string, name string) string {
+	for i, f := range a {
+		switch {
+		case f == "--":
+			return ""
+		case (f == "-"+name || f == "--"+name) && i+1 < len(a):
+			return a[i+1]
+		case strings.HasPrefix(f, "-"+name+"="), strings.HasPrefix(f, "--"+name+"="):
+			return f[strings.Index(f, "=")+1:]
+		}
+	}
+	return ""
+}
+
+// sessionDir returns where the client asked the command a to run,
+// as runRemote picks it: the -cwd a cpu client gives its remote,
+// or else PWD from the client's env.
+func sessionDir(a, env []string) string {
+	if d := argValue(a, "cwd"); d != "" {
+		return d
+	}
+	for _, e := range env {
+		if strings.HasPrefix(e, "PWD=") {
+			return e[len("PWD="):]
+		}
+	}
+	return ""
+}
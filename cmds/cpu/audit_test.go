@@ -0,0 +1,24 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import "testing"
+
+func TestSessionDir(t *testing.T) {
+	for _, tt := range []struct {
+		a, env []string
+		want   string
+	}{
+		{a: []string{"cpu", "-remote", "-cwd", "/a", "--", "ls"}, env: []string{"PWD=/b"}, want: "/a"},
+		{a: []string{"cpu", "-remote", "--cwd=/a", "--", "ls"}, want: "/a"},
+		{a: []string{"cpu", "-remote", "--", "ls"}, env: []string{"HOME=/h", "PWD=/b"}, want: "/b"},
+		{a: []string{"cpu", "-remote", "--", "ls", "-cwd", "/a"}, env: []string{"PWD=/b"}, want: "/b"},
+		{a: []string{"date"}},
+	} {
+		if got := sessionDir(tt.a, tt.env); got != tt.want {
+			t.Errorf("sessionDir(%q, %q): got %q, want %q", tt.a, tt.env, got, tt.want)
+		}
+	}
+}
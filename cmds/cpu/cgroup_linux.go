@@ -0,0 +1,169 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io/ioutil"
+	"log"
+	"os"
+	"os/exec"
+	"path/filepath"
+	"strconv"
+	"strings"
+	"sync/atomic"
+	"syscall"
+)
+
+var (
+	memoryMax = flag.String("memorymax", "", "cgroup v2 memory.max for each session, e.g. 1G (default no limit)")
+	cpuMax    = flag.String("cpumax", "", "cgroup v2 cpu.max for each session, e.g. \"50000 100000\" for half a CPU (default no limit)")
+)
+
+const cgroupRoot = "/sys/fs/cgroup"
+
+var (
+	// cgroupBase holds a cgroup for each session.
+	cgroupBase = filepath.Join(cgroupRoot, "cpu")
+	cgroupSeq  uint64
+)
+
+// limit is a cgroup controller, the file that sets a session's
+// limit, and the value from the flag.
+type limit struct {
+	controller, file, value string
+}
+
+func limits() []limit {
+	var l []limit
+	if *memoryMax != "" {
+		l = append(l, limit{"memory", "memory.max", *memoryMax})
+	}
+	if *cpuMax != "" {
+		l = append(l, limit{"cpu", "cpu.max", *cpuMax})
+	}
+	return l
+}
+
+// controllers checks that cgroup v2 has the controllers for
+// the limits we were asked for, and returns them as they are
+// written to cgroup.subtree_control.
+func controllers() ([]string, error) {
+	b, err := ioutil.ReadFile(filepath.Join(cgroupRoot, "cgroup.controllers"))
+	if err != nil {
+		return nil, fmt.Errorf("-memorymax and -cpumax need cgroup v2 on %v: %v", cgroupRoot, err)
+	}
+	have := strings.Fields(string(b))
+	var ctl []string
+	for _, c := range limits() {
+		found := false
+		for _, h := range have {
+			found = found || h == c.controller
+		}
+		if !found {
+			return nil, fmt.Errorf("cgroup controller %q is not available in %v", c.controller, cgroupRoot)
+		}
+		ctl = append(ctl, "+"+c.controller)
+	}
+	return ctl, nil
+}
+
+// cgroupSetup checks that cgroup v2 can set the limits we
+// were asked for, and makes cgroupBase. We want to fail here,
+// not run sessions without the limits.
+func cgroupSetup() error {
+	if len(limits()) == 0 {
+		return nil
+	}
+	ctl, err := controllers()
+	if err != nil {
+		return err
+	}
+	if err := os.MkdirAll(cgroupBase, 0755); err != nil {
+		return err
+	}
+	// The controllers have to be enabled at each level down.
+	for _, d := range []string{cgroupRoot, cgroupBase} {
+		if err := ioutil.WriteFile(filepath.Join(d, "cgroup.subtree_control"), []byte(strings.Join(ctl, " ")), 0); err != nil {
+			return fmt.Errorf("enabling %v in %v: %v", ctl, d, err)
+		}
+	}
+	return nil
+}
+
+// cgroup is the cgroup of one session, or "" if there are no limits.
+type cgroup string
+
+func newCgroup() (cgroup, error) {
+	l := limits()
+	if len(l) == 0 {
+		return "", nil
+	}
+	d := filepath.Join(cgroupBase, strconv.FormatUint(atomic.AddUint64(&cgroupSeq, 1), 10))
+	if err := os.Mkdir(d, 0755); err != nil {
+		return "", err
+	}
+	c := cgroup(d)
+	for _, f := range l {
+		if err := ioutil.WriteFile(filepath.Join(d, f.file), []byte(f.value), 0); err != nil {
+			c.remove()
+			return "", fmt.Errorf("setting %v to %q: %v", f.file, f.value, err)
+		}
+	}
+	return c, nil
+}
+
+// cgroupEnv holds the cgroup a command is to run in. It makes
+// us a shim that joins the cgroup and then execs the command, so
+// the command is limited from its first instruction on, and so
+// is everything it starts.
+const cgroupEnv = "CPU_CGROUP"
+
+// This runs before init, and so before flag.Parse: the arguments
+// are the command's, not ours.
+var _ = cgroupExec()
+
+func cgroupExec() bool {
+	d, ok := os.LookupEnv(cgroupEnv)
+	if !ok {
+		return false
+	}
+	os.Unsetenv(cgroupEnv)
+	if len(os.Args) < 3 {
+		fmt.Fprintf(os.Stderr, "cpu: %v is set, but there is no command\n", cgroupEnv)
+		os.Exit(1)
+	}
+	if err := ioutil.WriteFile(filepath.Join(d, "cgroup.procs"), []byte(strconv.Itoa(os.Getpid())), 0); err != nil {
+		fmt.Fprintf(os.Stderr, "cpu: joining cgroup %v: %v\n", d, err)
+		os.Exit(1)
+	}
+	err := syscall.Exec(os.Args[1], os.Args[2:], os.Environ())
+	fmt.Fprintf(os.Stderr, "cpu: %v: %v\n", os.Args[1], err)
+	os.Exit(127)
+	return true
+}
+
+// wrap makes cmd start through the cgroupExec shim, so that it
+// runs in c. Call it once cmd.Path and cmd.Env are set.
+func (c cgroup) wrap(cmd *exec.Cmd) {
+	if c == "" {
+		return
+	}
+	cmd.Args = append([]string{"cpu-cgroup", cmd.Path}, cmd.Args...)
+	cmd.Path = "/proc/self/exe"
+	cmd.Env = append(cmd.Env, cgroupEnv+"="+string(c))
+}
+
+// remove removes c. That fails if a process the session left
+// behind is still in it, in which case the cgroup stays.
+func (c cgroup) remove() {
+	if c == "" {
+		return
+	}
+	if err := os.Remove(string(c)); err != nil {
+		log.Printf("Warning: can't remove cgroup %v: %v", c, err)
+	}
+}
@@ -0,0 +1,124 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io/ioutil"
+	"net"
+	"path/filepath"
+	"strings"
+
+	"github.com/gliderlabs/ssh"
+)
+
+var checkOnly = flag.Bool("check", false, "check the server's flags, config file, keys and addresses, then exit, 0 if all is well")
+
+// check does what the server does before it serves, as far as
+// it can, and returns the first error. init has already read
+// the config file. The listeners are made and closed again, so
+// this fails if another server has the address.
+func check() error {
+	if *hostKeyFile != "" {
+		var s ssh.Server
+		if err := s.SetOption(ssh.HostKeyFile(*hostKeyFile)); err != nil {
+			return fmt.Errorf("host key %v: %v", *hostKeyFile, err)
+		}
+	}
+	if err := checkKeys(*pubKeyFile); err != nil {
+		return err
+	}
+	if *allowCmd != "" {
+		for _, p := range strings.Split(*allowCmd, ":") {
+			if !filepath.IsAbs(p) {
+				return fmt.Errorf("-allowcmd: %q is not an absolute path, so it can never match", p)
+			}
+		}
+	}
+	if *runAs != "" {
+		if _, err := credential(*runAs); err != nil {
+			return fmt.Errorf("-runas %v: %v", *runAs, err)
+		}
+	}
+	for _, e := range []struct{ name, patterns string }{{"envallow", *envAllow}, {"envdeny", *envDeny}} {
+		for _, p := range strings.Split(e.patterns, ":") {
+			if _, err := filepath.Match(p, ""); err != nil {
+				return fmt.Errorf("-%v: %q: %v", e.name, p, err)
+			}
+		}
+	}
+	if len(limits()) > 0 {
+		if _, err := controllers(); err != nil {
+			return err
+		}
+	}
+	if *auditLog != "" {
+		if err := openAudit(*auditLog); err != nil {
+			return err
+		}
+	}
+	for _, h := range []struct{ name, addr string }{{"health", *healthAddr}, {"metrics", *metricsAddr}} {
+		if h.addr == "" {
+			continue
+		}
+		l, err := listenHTTP(h.name, h.addr)
+		if err != nil {
+			return err
+		}
+		l.Close()
+	}
+	// listenAll can't tell if -connect is any good; dialing
+	// it is the only way, and there may be no client yet.
+	if *connect != "" {
+		if err := checkConnect(*connect); err != nil {
+			return err
+		}
+	}
+	ls, err := listenAll()
+	if err != nil {
+		return err
+	}
+	for _, l := range ls {
+		v("check: can listen on %v %v", l.Addr().Network(), l.Addr())
+		l.Close()
+	}
+	return nil
+}
+
+// checkConnect checks that a, the -connect address, is one the
+// server can dial.
+func checkConnect(a string) error {
+	n := *network
+	if i := strings.Index(a, "!"); i >= 0 {
+		n, a = a[:i], a[i+1:]
+	}
+	switch n {
+	case "tcp", "tcp4", "tcp6":
+		if _, err := net.ResolveTCPAddr(n, a); err != nil {
+			return fmt.Errorf("-connect %v: %v", a, err)
+		}
+	case "unix":
+		if a == "" {
+			return fmt.Errorf("-connect: no unix socket path")
+		}
+	default:
+		return fmt.Errorf("-connect: the server can't dial %v", n)
+	}
+	return nil
+}
+
+// checkKeys checks that the authorized keys file has a key in it.
+// ParseAuthorizedKey skips lines it can't parse, as sshd does.
+func checkKeys(file string) error {
+	data, err := ioutil.ReadFile(file)
+	if err != nil {
+		return err
+	}
+	if _, _, _, _, err := ssh.ParseAuthorizedKey(data); err != nil {
+		return fmt.Errorf("%v: %v", file, err)
+	}
+	return nil
+}
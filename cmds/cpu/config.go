@@ -0,0 +1,121 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io/ioutil"
+	"strconv"
+	"strings"
+)
+
+var configFile = flag.String("config", "", "file of flag settings, one name = value per line; flags on the command line win")
+
+// loadConfig sets flags from file, which has lines like
+//	sp = "2222" # the usual
+//	maxsessions = 10
+//	d = true
+// and # comments. That is the part of TOML one needs to set
+// flags, so a flat TOML file works. Flags named in cmdline, the
+// ones set on the command line, are left alone.
+func loadConfig(file string, cmdline map[string]bool) error {
+	b, err := ioutil.ReadFile(file)
+	if err != nil {
+		return err
+	}
+	settings, err := parseConfig(file, b)
+	if err != nil {
+		return err
+	}
+	for _, s := range settings {
+		if flag.Lookup(s.name) == nil {
+			return fmt.Errorf("%v:%d: no such flag %q", file, s.line, s.name)
+		}
+		if cmdline[s.name] {
+			continue
+		}
+		if err := flag.Set(s.name, s.value); err != nil {
+			return fmt.Errorf("%v:%d: %v", file, s.line, err)
+		}
+	}
+	return nil
+}
+
+// setFlags returns the names of the flags that have been set.
+// Before loadConfig, those are the ones on the command line.
+func setFlags() map[string]bool {
+	set := map[string]bool{}
+	flag.Visit(func(f *flag.Flag) {
+		set[f.Name] = true
+	})
+	return set
+}
+
+// setting is one name = value line of a config file.
+type setting struct {
+	name, value string
+	line        int
+}
+
+// parseConfig parses the config file b; file is for errors.
+func parseConfig(file string, b []byte) ([]setting, error) {
+	var settings []setting
+	for i, l := range strings.Split(string(b), "\n") {
+		l = strings.TrimSpace(l)
+		if l == "" || l[0] == '#' {
+			continue
+		}
+		kv := strings.SplitN(l, "=", 2)
+		if len(kv) != 2 {
+			return nil, fmt.Errorf("%v:%d: want name = value", file, i+1)
+		}
+		val, err := configValue(strings.TrimSpace(kv[1]))
+		if err != nil {
+			return nil, fmt.Errorf("%v:%d: %v", file, i+1, err)
+		}
+		settings = append(settings, setting{name: strings.TrimSpace(kv[0]), value: val, line: i + 1})
+	}
+	return settings, nil
+}
+
+// configValue returns the value v, unquoted, without any #
+// comment after it. A # inside quotes is part of the value.
+func configValue(v string) (string, error) {
+	var val, rest string
+	switch {
+	case strings.HasPrefix(v, "'"):
+		i := strings.Index(v[1:], "'")
+		if i < 0 {
+			return "", fmt.Errorf("unterminated string %v", v)
+		}
+		val, rest = v[1:i+1], v[i+2:]
+	case strings.HasPrefix(v, `"`):
+		// Find the closing quote, skipping escaped ones.
+		i := 1
+		for ; i < len(v) && v[i] != '"'; i++ {
+			if v[i] == '\\' {
+				i++
+			}
+		}
+		if i >= len(v) {
+			return "", fmt.Errorf("unterminated string %v", v)
+		}
+		var err error
+		if val, err = strconv.Unquote(v[:i+1]); err != nil {
+			return "", err
+		}
+		rest = v[i+1:]
+	default:
+		if i := strings.Index(v, "#"); i >= 0 {
+			v = v[:i]
+		}
+		return strings.TrimSpace(v), nil
+	}
+	if rest = strings.TrimSpace(rest); rest != "" && rest[0] != '#' {
+		return "", fmt.Errorf("junk after string: %v", rest)
+	}
+	return val, nil
+}
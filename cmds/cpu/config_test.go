@@ -0,0 +1,136 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"io/ioutil"
+	"os"
+	"path/filepath"
+	"reflect"
+	"testing"
+)
+
+func TestConfigValue(t *testing.T) {
+	for _, tt := range []struct {
+		v, want string
+		err     bool
+	}{
+		{v: "2222", want: "2222"},
+		{v: "2222 # the usual", want: "2222"},
+		{v: "2222#", want: "2222"},
+		{v: "", want: ""},
+		{v: `"/tmp/cpu"`, want: "/tmp/cpu"},
+		{v: `"a # b" # c`, want: "a # b"},
+		{v: `"say \"hi\""`, want: `say "hi"`},
+		{v: `"tab\there"`, want: "tab\there"},
+		{v: `'a # b'`, want: "a # b"},
+		{v: `'c:\dir'`, want: `c:\dir`},
+		{v: `''`, want: ""},
+		{v: `"open`, err: true},
+		{v: `"ends in \"`, err: true},
+		{v: `'open`, err: true},
+		{v: `"a" b`, err: true},
+		{v: `'a'b`, err: true},
+	} {
+		got, err := configValue(tt.v)
+		if tt.err {
+			if err == nil {
+				t.Errorf("configValue(%q): got %q, want error", tt.v, got)
+			}
+			continue
+		}
+		if err != nil {
+			t.Errorf("configValue(%q): %v", tt.v, err)
+			continue
+		}
+		if got != tt.want {
+			t.Errorf("configValue(%q): got %q, want %q", tt.v, got, tt.want)
+		}
+	}
+}
+
+func TestParseConfig(t *testing.T) {
+	for _, tt := range []struct {
+		name string
+		in   string
+		want []setting
+		err  bool
+	}{
+		{name: "empty"},
+		{name: "comments", in: "# all\n\n   # comments\n"},
+		{
+			name: "settings",
+			in:   "sp = \"2222\" # the usual\n\nmaxsessions=10\n  d = true  \n",
+			want: []setting{{"sp", "2222", 1}, {"maxsessions", "10", 3}, {"d", "true", 4}},
+		},
+		{name: "= in value", in: `mountopts = "a=b"`, want: []setting{{"mountopts", "a=b", 1}}},
+		{name: "no =", in: "d\n", err: true},
+		{name: "bad string", in: "sp = 22\nbin = \"cpu\n", err: true},
+	} {
+		t.Run(tt.name, func(t *testing.T) {
+			got, err := parseConfig("cpu.toml", []byte(tt.in))
+			if tt.err {
+				if err == nil {
+					t.Fatalf("parseConfig: got %v, want error", got)
+				}
+				return
+			}
+			if err != nil {
+				t.Fatalf("parseConfig: %v", err)
+			}
+			if !reflect.DeepEqual(got, tt.want) {
+				t.Errorf("parseConfig: got %v, want %v", got, tt.want)
+			}
+		})
+	}
+}
+
+func TestLoadConfig(t *testing.T) {
+	defer func(m int, q bool) {
+		*maxSessions, *queue = m, q
+	}(*maxSessions, *queue)
+	d, err := ioutil.TempDir("", "cpu")
+	if err != nil {
+		t.Fatal(err)
+	}
+	defer os.RemoveAll(d)
+	for _, tt := range []struct {
+		name    string
+		in      string
+		cmdline map[string]bool
+		max     int
+		queue   bool
+		err     bool
+	}{
+		{name: "good", in: "maxsessions = 7 # or so\nqueue = true\n", max: 7, queue: true},
+		{name: "command line wins", in: "maxsessions = 7\nqueue = true\n", cmdline: map[string]bool{"maxsessions": true}, queue: true},
+		{name: "no such flag", in: "maxsessions = 7\nnosuchflag = 1\n", err: true},
+		{name: "bad value", in: "maxsessions = seven\n", err: true},
+	} {
+		t.Run(tt.name, func(t *testing.T) {
+			*maxSessions, *queue = 0, false
+			f := filepath.Join(d, tt.name)
+			if err := ioutil.WriteFile(f, []byte(tt.in), 0644); err != nil {
+				t.Fatal(err)
+			}
+			err := loadConfig(f, tt.cmdline)
+			if tt.err {
+				if err == nil {
+					t.Fatalf("loadConfig: got nil, want error")
+				}
+				return
+			}
+			if err != nil {
+				t.Fatalf("loadConfig: %v", err)
+			}
+			if *maxSessions != tt.max || *queue != tt.queue {
+				t.Errorf("loadConfig: got -maxsessions %d -queue %v, want %d and %v", *maxSessions, *queue, tt.max, tt.queue)
+			}
+		})
+	}
+	if err := loadConfig(filepath.Join(d, "missing"), nil); err == nil {
+		t.Errorf("loadConfig of a missing file: got nil, want error")
+	}
+}
@@ -7,6 +7,8 @@ package main
 import (
 	"bytes"
 	"context"
+	"crypto/rand"
+	"crypto/rsa"
 	"flag"
 	"fmt"
 	"io"
@@ -15,9 +17,16 @@ import (
 	"net"
 	"os"
 	"os/exec"
+	"os/signal"
+	"os/user"
 	"path/filepath"
+	"runtime"
+	"strconv"
 	"strings"
+	"sync"
+	"sync/atomic"
 	"syscall"
+	"time"
 	"unsafe"
 
 	// We use this ssh because it implements port redirection.
@@ -35,23 +44,29 @@ import (
 var (
 	// For the ssh server part
 	hostKeyFile = flag.String("hk", "" /*"/etc/ssh/ssh_host_rsa_key"*/, "file for host key")
-	pubKeyFile  = flag.String("pk", "key.pub", "file for public key")
+	pubKeyFile  = flag.String("pk", "key.pub", "file of public keys allowed to connect, in authorized_keys format")
 	port        = flag.String("sp", "2222", "ssh default port")
+	addr        = flag.String("addr", "", "comma separated addresses for the server to listen on, each on -network or given as net!addr (default :port, or /tmp/cpu.sock for unix; vsock is cid:port)")
 
 	debug     = flag.Bool("d", false, "enable debug prints")
 	runAsInit = flag.Bool("init", false, "run as init (Debug only; normal test is if we are pid 1")
 	v         = func(string, ...interface{}) {}
 	remote    = flag.Bool("remote", false, "indicates we are the remote side of the cpu session")
-	network   = flag.String("network", "tcp", "network to use")
+	network   = flag.String("network", "tcp", "network to use (tcp, tcp4, tcp6, unix or vsock)")
 	keyFile   = flag.String("key", filepath.Join(os.Getenv("HOME"), ".ssh/cpu_rsa"), "key file")
 	srv9p     = flag.String("srv", "", "what server to run -- to use internal servers, leave this empty")
 	bin       = flag.String("bin", "cpu", "path of cpu binary")
 	port9p    = flag.String("port9p", "", "port9p # on remote machine for 9p mount")
 	dbg9p     = flag.Bool("dbg9p", false, "show 9p io")
 	root      = flag.String("root", "/", "9p root")
-	bindover  = flag.String("bindover", "/lib:/lib64:/lib32:/usr:/bin:/etc:/home", ": separated list of directories in /tmp/cpu to bind over /")
+	bindover  = flag.String("bindover", "/lib:/lib64:/lib32:/usr:/bin:/etc:/home", ": separated list of directories in -mountpoint to bind over /")
 	mountopts = flag.String("mountopts", "", "Extra options to add to the 9p mount")
 	msize     = flag.Int("msize", 1048576, "msize to use")
+	runAs     = flag.String("runas", "", "user the server runs the commands of cpu clients as; other clients are refused (default: leave them as they are)")
+	grace     = flag.Duration("grace", 5*time.Second, "how long the server waits for sessions to end on SIGINT or SIGTERM, before it kills their process groups")
+	cwd       = flag.String("cwd", "", "directory to run the remote command in (default the current directory)")
+	mntpt     = flag.String("mountpoint", "/tmp/cpu", "where the remote mounts our files; it is made if it is under /tmp, and must already be a directory anywhere else")
+	readOnly  = flag.Bool("ro", false, "mount our files read-only on the remote, so the remote command can't change them")
 )
 
 func verbose(f string, a ...interface{}) {
@@ -59,10 +74,18 @@ func verbose(f string, a ...interface{}) {
 }
 
 func dial(n, a string, config *ossh.ClientConfig) (*ossh.Client, error) {
-	client, err := ossh.Dial(n, a, config)
+	dial := ossh.Dial
+	if *reverse {
+		dial = reverseDial
+	}
+	client, err := dial(n, a, config)
 	if err != nil {
 		return nil, fmt.Errorf("Failed to dial: %v", err)
 	}
+	if err := checkVersion(string(client.ServerVersion())); err != nil {
+		client.Close()
+		return nil, err
+	}
 	return client, nil
 }
 
@@ -102,6 +125,7 @@ func config(kf string) (*ossh.ClientConfig, error) {
 			ossh.PublicKeys(signer),
 		},
 		HostKeyCallback: cb,
+		ClientVersion:   "SSH-2.0-" + versionString,
 	}
 	return config, nil
 }
@@ -136,6 +160,171 @@ func dropPrivs() error {
 	return unix.Setregid(-1, gid)
 }
 
+// credential returns the Credential to run a command as user n,
+// with the user's supplementary groups, as initgroups would.
+func credential(n string) (*syscall.Credential, error) {
+	u, err := user.Lookup(n)
+	if err != nil {
+		return nil, err
+	}
+	uid, err := strconv.ParseUint(u.Uid, 10, 32)
+	if err != nil {
+		return nil, err
+	}
+	gid, err := strconv.ParseUint(u.Gid, 10, 32)
+	if err != nil {
+		return nil, err
+	}
+	cred := &syscall.Credential{Uid: uint32(uid), Gid: uint32(gid)}
+	// An empty Groups still means setgroups(0, NULL), so if we
+	// can't get the groups the command has fewer, not root's.
+	gids, err := u.GroupIds()
+	if err != nil {
+		log.Printf("Warning: no supplementary groups for %v: %v", n, err)
+	}
+	for _, g := range gids {
+		id, err := strconv.ParseUint(g, 10, 32)
+		if err != nil {
+			return nil, err
+		}
+		cred.Groups = append(cred.Groups, uint32(id))
+	}
+	return cred, nil
+}
+
+// formatCredential and parseCredential pass a Credential from
+// the server to cpu -remote as uid:gid:group,group,...: numbers
+// the server looked up in its own /etc, since by the time cpu
+// -remote runs the command, /etc may be the client's.
+func formatCredential(c *syscall.Credential) string {
+	g := make([]string, len(c.Groups))
+	for i, id := range c.Groups {
+		g[i] = strconv.FormatUint(uint64(id), 10)
+	}
+	return fmt.Sprintf("%d:%d:%s", c.Uid, c.Gid, strings.Join(g, ","))
+}
+
+func parseCredential(s string) (*syscall.Credential, error) {
+	f := strings.Split(s, ":")
+	if len(f) != 3 {
+		return nil, fmt.Errorf("%q is not uid:gid:groups", s)
+	}
+	var ids []uint32
+	for i, n := range append([]string{f[0], f[1]}, strings.Split(f[2], ",")...) {
+		if n == "" && i >= 2 {
+			continue
+		}
+		id, err := strconv.ParseUint(n, 10, 32)
+		if err != nil {
+			return nil, fmt.Errorf("%q is not uid:gid:groups: %v", s, err)
+		}
+		ids = append(ids, uint32(id))
+	}
+	return &syscall.Credential{Uid: ids[0], Gid: ids[1], Groups: ids[2:]}, nil
+}
+
+// self returns the path of the command n, as looked up here, and
+// whether it is this cpu binary.
+func self(n string) (string, bool, error) {
+	p, err := resolve(n)
+	if err != nil {
+		return "", false, err
+	}
+	fi, err := os.Stat(p)
+	if err != nil {
+		return "", false, err
+	}
+	me, err := os.Stat("/proc/self/exe")
+	if err != nil {
+		return "", false, err
+	}
+	return p, os.SameFile(fi, me), nil
+}
+
+// remoteArgs makes the arguments of a cpu -remote command again,
+// from the flags that clients of a -runas or -allowcmd server may
+// choose, and refuses any others. cpu -remote is root until it
+// runs the client's command, so -bindover, -mountpoint, -mountopts,
+// -config and the rest stay as the server has them.
+func remoteArgs(args []string) ([]string, error) {
+	f := flag.NewFlagSet("cpu -remote", flag.ContinueOnError)
+	f.SetOutput(ioutil.Discard)
+	remote := f.Bool("remote", false, "")
+	port := f.String("port9p", "", "")
+	f.String("bin", "", "")
+	dir := f.String("cwd", "", "")
+	ro := f.Bool("ro", false, "")
+	fds := &fdFiles{}
+	f.Var(fds, "fd", "")
+	if err := f.Parse(args); err != nil {
+		return nil, fmt.Errorf("cpu -remote: %v; this server only takes -port9p, -bin, -cwd, -ro and -fd", err)
+	}
+	if !*remote {
+		return nil, fmt.Errorf("only cpu -remote runs on this server")
+	}
+	if _, err := strconv.ParseUint(*port, 10, 16); err != nil {
+		return nil, fmt.Errorf("-port9p %q: %v", *port, err)
+	}
+	r := []string{"-remote", "-port9p", *port}
+	if *dir != "" {
+		r = append(r, "-cwd", *dir)
+	}
+	if *ro {
+		r = append(r, "-ro")
+	}
+	for _, s := range *fds {
+		r = append(r, "-fd", s)
+	}
+	return append(append(r, "--"), f.Args()...), nil
+}
+
+// workDir returns the directory to run the command in: dir under
+// the mount point, where all the client's files are, or else, so
+// that the odd command still runs, dir on the server, with a
+// warning, since it is not the client's.
+func workDir(dir string) (string, error) {
+	if dir == "" {
+		return "", nil
+	}
+	if d := filepath.Join(*mntpt, dir); isDir(d) {
+		return d, nil
+	}
+	if isDir(dir) {
+		log.Printf("Warning: working directory %v is not in %v; using %v on the server", dir, *mntpt, dir)
+		return dir, nil
+	}
+	return "", fmt.Errorf("working directory %v: no such directory, in %v or /", dir, *mntpt)
+}
+
+func isDir(d string) bool {
+	fi, err := os.Stat(d)
+	return err == nil && fi.IsDir()
+}
+
+// private is set if init got us a private mount namespace.
+// Without one, our mounts would show up on the host.
+var private bool
+
+// mountPoint cleans -mountpoint, and checks that it is under /tmp
+// or is a directory that is already there.
+func mountPoint() error {
+	*mntpt = filepath.Clean(*mntpt)
+	if !filepath.IsAbs(*mntpt) {
+		return fmt.Errorf("-mountpoint %v: not an absolute path", *mntpt)
+	}
+	if strings.HasPrefix(*mntpt, "/tmp/") {
+		return nil
+	}
+	fi, err := os.Stat(*mntpt)
+	if err != nil {
+		return fmt.Errorf("-mountpoint %v: %v; only directories under /tmp are made", *mntpt, err)
+	}
+	if !fi.IsDir() {
+		return fmt.Errorf("-mountpoint %v: not a directory", *mntpt)
+	}
+	return nil
+}
+
 // start up a namespace. We must
 // mkdir /tmp/cpu on the remote machine
 // issue the mount command
@@ -159,10 +348,20 @@ func runRemote(cmd, port9p string) error {
 		}
 	}
 
-	// It's true we are making this directory while still root.
+	if !private {
+		return fmt.Errorf("no private mount namespace; not mounting 9p where the host would see it")
+	}
+
+	// It's true we are making these directories while still root.
 	// This ought to be safe as it is a private namespace mount.
-	for _, n := range []string{"/tmp/cpu", "/tmp/local", "/tmp/merge", "/tmp/root", "/home"} {
-		if err := os.MkdirAll(n, 0666); err != nil && !os.IsExist(err) {
+	// We only make a -mountpoint under /tmp; anywhere else, it
+	// must be there already, so a client can't make root
+	// directories wherever it likes.
+	if err := mountPoint(); err != nil {
+		return err
+	}
+	for _, n := range []string{*mntpt, "/tmp/local", "/tmp/merge", "/tmp/root", "/home"} {
+		if err := os.MkdirAll(n, 0755); err != nil && !os.IsExist(err) {
 			log.Println(err)
 		}
 	}
@@ -172,13 +371,27 @@ func runRemote(cmd, port9p string) error {
 		user = "nouser"
 	}
 	flags := uintptr(unix.MS_NODEV | unix.MS_NOSUID)
+	// The kernel enforces MS_RDONLY, for this mount and any bind
+	// of it, whatever the 9p server would let us do.
+	if *readOnly {
+		flags |= unix.MS_RDONLY
+	}
 	opts := fmt.Sprintf("version=9p2000.L,trans=tcp,port=%v,uname=%v,debug=0,msize=%d", port9p, user, *msize)
 	if *mountopts != "" {
 		opts += "," + *mountopts
 	}
-	if err := unix.Mount("127.0.0.1", "/tmp/cpu", "9p", flags, opts); err != nil {
+	if err := unix.Mount("127.0.0.1", *mntpt, "9p", flags, opts); err != nil {
 		return fmt.Errorf("9p mount %v", err)
 	}
+	if *readOnly {
+		var st unix.Statfs_t
+		if err := unix.Statfs(*mntpt, &st); err != nil {
+			return fmt.Errorf("can't tell if 9p mount on %v is read-only: %v", *mntpt, err)
+		}
+		if st.Flags&unix.ST_RDONLY == 0 {
+			return fmt.Errorf("9p mount on %v is not read-only", *mntpt)
+		}
+	}
 
 	// Further, bind / onto /tmp/local so a non-hacked-on version may be visible.
 	if err := unix.Mount("/", "/tmp/local", "", syscall.MS_BIND, ""); err != nil {
@@ -187,10 +400,10 @@ func runRemote(cmd, port9p string) error {
 
 	var overlaid bool
 	if util.FindFileSystem("overlay") == nil {
-		if err := unix.Mount("overlay", "/tmp/root", "overlay", unix.MS_MGC_VAL, "lowerdir=/tmp/cpu,upperdir=/tmp/local,workdir=/tmp/merge"); err == nil {
+		if err := unix.Mount("overlay", "/tmp/root", "overlay", unix.MS_MGC_VAL, "lowerdir="+*mntpt+",upperdir=/tmp/local,workdir=/tmp/merge"); err == nil {
 			//overlaid = true
 		} else {
-			log.Printf("Overlayfs mount failed: %v. Proceeding with selective mounts from /tmp/cpu into /", err)
+			log.Printf("Overlayfs mount failed: %v. Proceeding with selective mounts from %v into /", err, *mntpt)
 		}
 	}
 	if !overlaid && *bindover != "" {
@@ -201,7 +414,7 @@ func runRemote(cmd, port9p string) error {
 		// bind *may* hide local resources but for now it's the least worst option.
 		dirs := strings.Split(*bindover, ":")
 		for _, n := range dirs {
-			t := filepath.Join("/tmp/cpu", n)
+			t := filepath.Join(*mntpt, n)
 			if err := unix.Mount(t, n, "", syscall.MS_BIND, ""); err != nil {
 				log.Printf("Warning: mounting %v on %v failed: %v", t, n, err)
 			} else {
@@ -217,9 +430,54 @@ func runRemote(cmd, port9p string) error {
 	// The unmount happens for free since we unshared.
 	v("runRemote: command is %q", cmd)
 	f := strings.Fields(cmd)
+	if len(f) == 0 {
+		return fmt.Errorf("no command to run")
+	}
+	// Older clients don't send -cwd; PWD is where they were.
+	dir := *cwd
+	if dir == "" {
+		dir = os.Getenv("PWD")
+	}
+	if dir, err = workDir(dir); err != nil {
+		return err
+	}
 	c := exec.Command(f[0], f[1:]...)
-	c.Stdin, c.Stdout, c.Stderr, c.Dir = os.Stdin, os.Stdout, os.Stderr, os.Getenv("PWD")
-	return c.Run()
+	c.Stdin, c.Stdout, c.Stderr, c.Dir = os.Stdin, os.Stdout, os.Stderr, dir
+	// The server sets CPU_RUNAS from its -runas. If we can't
+	// become that user, we must not run the command at all.
+	var cred *syscall.Credential
+	if n := os.Getenv("CPU_RUNAS"); n != "" {
+		if cred, err = parseCredential(n); err != nil {
+			return fmt.Errorf("can't run as %v: %v", n, err)
+		}
+		c.SysProcAttr = &syscall.SysProcAttr{Credential: cred}
+	}
+	// The -fd files are opened as the command's user, since we
+	// may be root and they are the client's to name.
+	if c.ExtraFiles, err = openFds(cred); err != nil {
+		return err
+	}
+	// They are for us, not the command.
+	for _, e := range serverEnv {
+		os.Unsetenv(e)
+	}
+	// no_new_privs is per thread, and the command is started
+	// from this one, which keeps it. With it, no setuid file,
+	// the host's or one bound over from the client, can take
+	// the command back to root.
+	if cred != nil {
+		runtime.LockOSThread()
+		if err := unix.Prctl(unix.PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0); err != nil {
+			return fmt.Errorf("no_new_privs: %v", err)
+		}
+	}
+	err = c.Start()
+	// The command has its own copies of the files now.
+	closeFds(c.ExtraFiles)
+	if err != nil {
+		return err
+	}
+	return c.Wait()
 }
 
 // srv on 5641.
@@ -268,6 +526,9 @@ func forward(l net.Listener, s net.Conn) error {
 
 // To make sure defer gets run and you tty is sane on exit
 func runClient(host, a string) error {
+	if err := checkMsize(); err != nil {
+		return err
+	}
 	c, err := config(*keyFile)
 	if err != nil {
 		return err
@@ -276,6 +537,12 @@ func runClient(host, a string) error {
 	if err != nil {
 		return err
 	}
+	if *pingEvery > 0 {
+		go ping(cl, *pingEvery)
+	}
+	if err := startTunnels(cl); err != nil {
+		return err
+	}
 	// Arrange port forwarding from remote ssh to our server.
 	// Request the remote side to open port 5640 on all interfaces.
 	// Note: cl.Listen returns a TCP listener with network is "tcp"
@@ -290,6 +557,7 @@ func runClient(host, a string) error {
 	}
 	port := ap[len(ap)-1]
 	v("listener %T %v addr %v port %v", l, l, l.Addr().String(), port)
+	l = msizeListener{l}
 
 	if *srv9p == "" {
 		go p9.NewServer(&cpu9p{path: *root}).Serve(l)
@@ -329,22 +597,101 @@ func env(s *ossh.Session) {
 	}
 }
 
-func shell(client *ossh.Client, a, port9p string) error {
-	t, err := termios.New()
-	if err != nil {
-		return err
+// winch passes changes in our window size on to the remote pty.
+func winch(s *ossh.Session) {
+	c := make(chan os.Signal, 1)
+	signal.Notify(c, unix.SIGWINCH)
+	defer signal.Stop(c)
+	for range c {
+		ws, err := termios.GetWinSize(os.Stdin.Fd())
+		if err != nil {
+			v("winch: %v", err)
+			continue
+		}
+		if err := s.WindowChange(int(ws.Row), int(ws.Col)); err != nil {
+			v("winch: %v", err)
+			return
+		}
 	}
-	r, err := t.Raw()
+}
+
+// sendSignals passes SIGINT, SIGQUIT and SIGTERM on to the
+// remote command instead of letting them kill cpu. With a pty,
+// ^C reaches the remote as a byte anyway; this covers kill(1)
+// and sessions without a pty.
+func sendSignals(s *ossh.Session) {
+	c := make(chan os.Signal, 1)
+	signal.Notify(c, unix.SIGINT, unix.SIGQUIT, unix.SIGTERM)
+	defer signal.Stop(c)
+	for sig := range c {
+		n := ossh.SIGTERM
+		switch sig {
+		case unix.SIGINT:
+			n = ossh.SIGINT
+		case unix.SIGQUIT:
+			n = ossh.SIGQUIT
+		}
+		if err := s.Signal(n); err != nil {
+			v("sendSignals: %v", err)
+			return
+		}
+	}
+}
+
+// remoteFlags returns the flags for cpu -remote beyond the ones
+// it has always had. Each is only there if it is in use, so that
+// a remote cpu that does not know it still works without it.
+func remoteFlags() (string, error) {
+	var opts string
+	// A relative -cwd is relative to where we are, since the
+	// remote sees our files. Without -cwd, the remote uses PWD.
+	if *cwd != "" {
+		dir, err := filepath.Abs(*cwd)
+		if err != nil {
+			return "", err
+		}
+		opts += fmt.Sprintf(" -cwd %q", dir)
+	}
+	if *mntpt != flag.Lookup("mountpoint").DefValue {
+		opts += fmt.Sprintf(" -mountpoint %q", *mntpt)
+	}
+	if *readOnly {
+		opts += " -ro"
+	}
+	fds, err := fdArgs()
 	if err != nil {
-		return err
+		return "", err
+	}
+	return opts + fds, nil
+}
+
+func shell(client *ossh.Client, a, port9p string) error {
+	// Only ask for a pty if stdin is a terminal. If it is not,
+	// e.g. cpu host cmd < file, plain pipes are what is wanted,
+	// and raw mode on /dev/tty would just mess up the user's tty.
+	_, err := termios.GetTermios(os.Stdin.Fd())
+	isTTY := err == nil
+	if isTTY {
+		t, err := termios.New()
+		if err != nil {
+			return err
+		}
+		r, err := t.Raw()
+		if err != nil {
+			return err
+		}
+		defer t.Set(r)
 	}
-	defer t.Set(r)
 	if *bin == "" {
 		if *bin, err = exec.LookPath("cpu"); err != nil {
 			return err
 		}
 	}
-	a = fmt.Sprintf("%v -remote -port9p %v -bin %v %v", *bin, port9p, *bin, a)
+	opts, err := remoteFlags()
+	if err != nil {
+		return err
+	}
+	a = fmt.Sprintf("%v -remote -port9p %v -bin %v%v %v", *bin, port9p, *bin, opts, a)
 	v("command is %q", a)
 	session, err := client.NewSession()
 	if err != nil {
@@ -352,15 +699,22 @@ func shell(client *ossh.Client, a, port9p string) error {
 	}
 	defer session.Close()
 	env(session)
-	// Set up terminal modes
-	modes := ossh.TerminalModes{
-		ossh.ECHO:          0,     // disable echoing
-		ossh.TTY_OP_ISPEED: 14400, // input speed = 14.4kbaud
-		ossh.TTY_OP_OSPEED: 14400, // output speed = 14.4kbaud
-	}
-	// Request pseudo terminal
-	if err := session.RequestPty("ansi", 40, 80, modes); err != nil {
-		log.Fatal("request for pseudo terminal failed: ", err)
+	if isTTY {
+		// Set up terminal modes
+		modes := ossh.TerminalModes{
+			ossh.ECHO:          0,     // disable echoing
+			ossh.TTY_OP_ISPEED: 14400, // input speed = 14.4kbaud
+			ossh.TTY_OP_OSPEED: 14400, // output speed = 14.4kbaud
+		}
+		h, w := 40, 80
+		if ws, err := termios.GetWinSize(os.Stdin.Fd()); err == nil {
+			h, w = int(ws.Row), int(ws.Col)
+		}
+		// Request pseudo terminal
+		if err := session.RequestPty("ansi", h, w, modes); err != nil {
+			return fmt.Errorf("request for pseudo terminal failed: %v", err)
+		}
+		go winch(session)
 	}
 	i, err := session.StdinPipe()
 	if err != nil {
@@ -391,6 +745,7 @@ func shell(client *ossh.Client, a, port9p string) error {
 	if err := session.Start(cmd); err != nil {
 		return fmt.Errorf("Failed to run %v: %v", a, err.Error())
 	}
+	go sendSignals(session)
 	go io.Copy(i, os.Stdin)
 	go io.Copy(os.Stdout, o)
 	go io.Copy(os.Stderr, e)
@@ -402,6 +757,11 @@ func shell(client *ossh.Client, a, port9p string) error {
 // single threaded.
 func init() {
 	flag.Parse()
+	if *configFile != "" {
+		if err := loadConfig(*configFile, setFlags()); err != nil {
+			log.Fatalf("config: %v", err)
+		}
+	}
 	if os.Getpid() == 1 {
 		*runAsInit, *debug = true, false
 	}
@@ -421,13 +781,15 @@ func init() {
 			slash = [...]byte{'/', 0}
 			flags = uintptr(unix.MS_PRIVATE | unix.MS_REC) // Thanks for nothing Linux.
 		)
-		if err := syscall.Unshare(syscall.CLONE_NEWNS); err != nil {
+		err := syscall.Unshare(syscall.CLONE_NEWNS)
+		if err != nil {
 			log.Printf("bad Unshare: %v", err)
 		}
 		_, _, err1 := syscall.RawSyscall6(unix.SYS_MOUNT, uintptr(unsafe.Pointer(&none[0])), uintptr(unsafe.Pointer(&slash[0])), 0, flags, 0, 0)
 		if err1 != 0 {
 			log.Printf("Warning: unshare failed (%v). There will be no private 9p mount", err1)
 		}
+		private = err == nil && err1 == 0
 		flags = 0
 		if err := unix.Mount("cpu", "/tmp", "tmpfs", flags, ""); err != nil {
 			log.Printf("Warning: tmpfs mount on /tmp (%v) failed. There will be no 9p mount", err)
@@ -440,21 +802,341 @@ func setWinsize(f *os.File, w, h int) {
 		uintptr(unsafe.Pointer(&struct{ h, w, x, y uint16 }{uint16(h), uint16(w), 0, 0})))
 }
 
+type contextKey string
+
+// identityKey is the context key for the comment of the key a
+// client authenticated with, which is the nearest thing we have
+// to a name for whoever holds it.
+const identityKey contextKey = "identity"
+
+// authorized reports whether key is one of the keys in the
+// authorized keys file, and if so returns its comment.
+func authorized(file string, key ssh.PublicKey) (string, bool) {
+	data, err := ioutil.ReadFile(file)
+	if err != nil {
+		log.Print(err)
+		return "", false
+	}
+	for len(data) > 0 {
+		allowed, comment, _, rest, err := ssh.ParseAuthorizedKey(data)
+		if err != nil {
+			break
+		}
+		if ssh.KeysEqual(key, allowed) {
+			if comment == "" {
+				comment = ossh.FingerprintSHA256(key)
+			}
+			return comment, true
+		}
+		data = rest
+	}
+	v("key %v not in %v", ossh.FingerprintSHA256(key), file)
+	return "", false
+}
+
+// sessions holds the processes started by handler, so that
+// they can be signalled when the server shuts down, and so that
+// the reaper in init mode leaves them to handler's cmd.Wait.
+var sessions = struct {
+	sync.Mutex
+	procs map[*os.Process]struct{}
+}{procs: map[*os.Process]struct{}{}}
+
+// sessionEnded is broadcast whenever a process leaves sessions.
+var sessionEnded = sync.NewCond(&sessions.Mutex)
+
+// track starts cmd with start and adds its process to sessions.
+// The lock is held across start, so the reaper can not see the
+// process exit before it is in sessions. The returned func
+// removes it; call it only once cmd has been waited for.
+func track(cmd *exec.Cmd, start func() error) (func(), error) {
+	sessions.Lock()
+	defer sessions.Unlock()
+	if err := start(); err != nil {
+		return nil, err
+	}
+	p := cmd.Process
+	sessions.procs[p] = struct{}{}
+	return func() {
+		sessions.Lock()
+		delete(sessions.procs, p)
+		sessions.Unlock()
+		sessionEnded.Broadcast()
+	}, nil
+}
+
+// waitSession waits until pid, if it is a session's, has left
+// sessions. It reports whether it was a session's.
+func waitSession(pid int) bool {
+	sessions.Lock()
+	defer sessions.Unlock()
+	owned := false
+	for {
+		found := false
+		for p := range sessions.procs {
+			if p.Pid == pid {
+				found = true
+				break
+			}
+		}
+		if !found {
+			return owned
+		}
+		owned = true
+		sessionEnded.Wait()
+	}
+}
+
+func numSessions() int {
+	sessions.Lock()
+	defer sessions.Unlock()
+	return len(sessions.procs)
+}
+
+// signalSessions sends sig to the process group of each session,
+// as hangup does, so what the commands started gets it too.
+func signalSessions(sig syscall.Signal) {
+	sessions.Lock()
+	defer sessions.Unlock()
+	for p := range sessions.procs {
+		if err := unix.Kill(-p.Pid, sig); err != nil {
+			v("signal %v to %d: %v", sig, -p.Pid, err)
+		}
+	}
+}
+
+// shutdown waits for SIGINT or SIGTERM. It then stops the server
+// accepting, asks the running sessions to end, gives them *grace
+// to do so, and kills and closes whatever is left.
+func shutdown(s *ssh.Server, done chan<- struct{}) {
+	c := make(chan os.Signal, 1)
+	signal.Notify(c, unix.SIGINT, unix.SIGTERM)
+	sig := <-c
+	log.Printf("Got %v, shutting down", sig)
+	signalSessions(unix.SIGTERM)
+	ctx, cancel := context.WithTimeout(context.Background(), *grace)
+	defer cancel()
+	if err := s.Shutdown(ctx); err != nil {
+		log.Printf("Sessions did not end within %v, killing them", *grace)
+		signalSessions(unix.SIGKILL)
+		s.Close()
+	}
+	close(done)
+}
+
+// sshSignals maps the signal names of RFC 4254 to signals.
+var sshSignals = map[ssh.Signal]syscall.Signal{
+	ssh.SIGABRT: unix.SIGABRT,
+	ssh.SIGALRM: unix.SIGALRM,
+	ssh.SIGFPE:  unix.SIGFPE,
+	ssh.SIGHUP:  unix.SIGHUP,
+	ssh.SIGILL:  unix.SIGILL,
+	ssh.SIGINT:  unix.SIGINT,
+	ssh.SIGKILL: unix.SIGKILL,
+	ssh.SIGPIPE: unix.SIGPIPE,
+	ssh.SIGQUIT: unix.SIGQUIT,
+	ssh.SIGSEGV: unix.SIGSEGV,
+	ssh.SIGTERM: unix.SIGTERM,
+	ssh.SIGUSR1: unix.SIGUSR1,
+	ssh.SIGUSR2: unix.SIGUSR2,
+}
+
+// deliverSignals delivers the signals the client sends on s to
+// the process group led by pid. The returned func stops it.
+func deliverSignals(s ssh.Session, pid int) func() {
+	c := make(chan ssh.Signal, 1)
+	done := make(chan struct{})
+	s.Signals(c)
+	go func() {
+		for {
+			select {
+			case sig := <-c:
+				n, ok := sshSignals[sig]
+				if !ok {
+					v("deliverSignals: unknown signal %q", sig)
+					continue
+				}
+				if err := unix.Kill(-pid, n); err != nil {
+					v("deliverSignals: kill(%d, %v): %v", -pid, n, err)
+				}
+			case <-done:
+				return
+			}
+		}
+	}()
+	return func() {
+		s.Signals(nil)
+		close(done)
+	}
+}
+
+// hangup ends the process group led by pid if the client goes
+// away while it runs: SIGTERM, then SIGKILL if it is still there
+// after *grace. The 9p mount is in the command's own namespace
+// and goes with it. Call the returned func once the command has
+// been waited for, so we never signal a recycled pid.
+func hangup(s ssh.Session, pid int) func() {
+	done := make(chan struct{})
+	go func() {
+		select {
+		case <-s.Context().Done():
+		case <-done:
+			return
+		}
+		log.Printf("Session for %v from %v: client went away, ending pid %d", s.User(), s.RemoteAddr(), pid)
+		if err := unix.Kill(-pid, unix.SIGTERM); err != nil {
+			v("hangup: kill(%d, SIGTERM): %v", -pid, err)
+		}
+		select {
+		case <-time.After(*grace):
+			log.Printf("Session for %v from %v: pid %d still there after %v, killing it", s.User(), s.RemoteAddr(), pid, *grace)
+			unix.Kill(-pid, unix.SIGKILL)
+		case <-done:
+		}
+	}()
+	return func() {
+		close(done)
+	}
+}
+
+// exitCode returns the exit status for the error from a command's
+// Wait, as a shell would report it: the process's exit code, or
+// 128 plus the signal that killed it. ssh clients decode an
+// exit-signal the same way.
+func exitCode(err error) int {
+	if err == nil {
+		return 0
+	}
+	e, ok := err.(*exec.ExitError)
+	if !ok {
+		return 1
+	}
+	if w, ok := e.Sys().(syscall.WaitStatus); ok && w.Signaled() {
+		return 128 + int(w.Signal())
+	}
+	return e.ExitCode()
+}
+
+// refuse ends session s before it has run anything, telling
+// both the log and the client why.
+func refuse(s ssh.Session, err error) {
+	log.Printf("Session for %v from %v: %v", s.User(), s.RemoteAddr(), err)
+	fmt.Fprintf(s.Stderr(), "cpu: %v\n", err)
+	s.Exit(1)
+}
+
 func handler(s ssh.Session) {
+	// Each session runs in its own goroutine; a panic in one
+	// should end that session, not the whole server.
+	defer func() {
+		if r := recover(); r != nil {
+			log.Printf("handler: recovered from panic: %v", r)
+			s.Exit(1)
+		}
+	}()
 	a := s.Command()
 	verbose("the handler is here, cmd is %v", a)
-	cmd := exec.Command(a[0], a[1:]...)
-	cmd.Env = append(cmd.Env, s.Environ()...)
+	if len(a) == 0 {
+		log.Printf("handler: no command given")
+		s.Exit(1)
+		return
+	}
+	log.Printf("Session for %v (%v) from %v: %q", s.User(), s.Context().Value(identityKey), s.RemoteAddr(), a)
+	au := newAuditRecord(s)
+	defer au.ended()
+	if ctx, ok := s.Context().(ssh.Context); ok {
+		if err := checkVersion(ctx.ClientVersion()); err != nil {
+			refuse(s, err)
+			return
+		}
+	}
+	// The command is looked up here, in our namespace, and run
+	// by the path we checked, not found again by exec.
+	path, args := a[0], a[1:]
+	if *allowCmd != "" {
+		p, err := cmdAllowed(*allowCmd, a[0])
+		if err != nil {
+			refuse(s, err)
+			return
+		}
+		path = p
+	}
+	// cpu -remote starts as root, so on a -runas or -allowcmd
+	// server the client does not get to say how it sets up.
+	if *runAs != "" || *allowCmd != "" {
+		p, ok, err := self(path)
+		if err != nil {
+			refuse(s, err)
+			return
+		}
+		switch {
+		case ok:
+			if args, err = remoteArgs(args); err != nil {
+				refuse(s, err)
+				return
+			}
+			path = p
+		case *runAs != "":
+			refuse(s, fmt.Errorf("%v: only cpu -remote runs on a -runas server", a[0]))
+			return
+		}
+	}
+	release, err := startSession(s.Context())
+	if err != nil {
+		refuse(s, err)
+		return
+	}
+	defer release()
+	defer nineP(argValue(a, "port9p"))()
+	sessionCount.Add(1)
+	cmd := exec.Command(path, args...)
+	cmd.Args[0] = a[0]
+	au.Argv = cmd.Args
+	cmd.Env = filterEnv(s.Environ())
+	// We can't set the Credential ourselves: cpu -remote needs
+	// root for its mounts, and gives up root before it runs the
+	// client's command. So nothing else may run.
+	if *runAs != "" {
+		cred, err := credential(*runAs)
+		if err != nil {
+			refuse(s, fmt.Errorf("can't run as %v: %v", *runAs, err))
+			return
+		}
+		cmd.Env = append(cmd.Env, "CPU_RUNAS="+formatCredential(cred))
+	}
+	// cpu -remote runs whatever the client sends it, so if cpu
+	// is allowed, it is all that is; main makes sure that it
+	// does not run as anything else.
+	if *allowCmd != "" {
+		cmd.Env = append(cmd.Env, "CPU_ALLOWCMD=1")
+	}
+	cg, err := newCgroup()
+	if err != nil {
+		refuse(s, err)
+		return
+	}
+	defer cg.remove()
+	cg.wrap(cmd)
 	ptyReq, winCh, isPty := s.Pty()
 	verbose("the command is %v", *cmd)
 	if isPty {
 		cmd.Env = append(cmd.Env, fmt.Sprintf("TERM=%s", ptyReq.Term))
-		f, err := pty.Start(cmd)
+		var f *os.File
+		var untrack func()
+		untrack, err = track(cmd, func() (err error) {
+			f, err = pty.Start(cmd)
+			return err
+		})
 		verbose("command started with pty")
 		if err != nil {
-			log.Print(err)
+			refuse(s, err)
 			return
 		}
+		defer f.Close()
+		defer untrack()
+		au.started(cmd.Process.Pid)
+		defer deliverSignals(s, cmd.Process.Pid)()
+		stop := hangup(s, cmd.Process.Pid)
 		go func() {
 			for win := range winCh {
 				setWinsize(f, win.Width, win.Height)
@@ -464,15 +1146,57 @@ func handler(s ssh.Session) {
 			io.Copy(f, s) // stdin
 		}()
 		io.Copy(s, f) // stdout
+		err = cmd.Wait()
+		stop()
 	} else {
-		cmd.Stdin, cmd.Stdout, cmd.Stderr = s, s, s
+		// Keep stderr on its own ssh stream so the client
+		// can tell it from stdout.
+		cmd.Stdin, cmd.Stdout, cmd.Stderr = s, s, s.Stderr()
+		// pty.Start makes the command a session leader; do the
+		// same here so signals can go to its process group.
+		cmd.SysProcAttr = &syscall.SysProcAttr{Setpgid: true}
 		verbose("running command without pty")
-		if err := cmd.Run(); err != nil {
-			log.Print(err)
+		var untrack func()
+		if untrack, err = track(cmd, cmd.Start); err != nil {
+			refuse(s, err)
 			return
 		}
+		defer untrack()
+		au.started(cmd.Process.Pid)
+		defer deliverSignals(s, cmd.Process.Pid)()
+		stop := hangup(s, cmd.Process.Pid)
+		err = cmd.Wait()
+		stop()
+	}
+	code := exitCode(err)
+	au.status = code
+	log.Printf("Session for %v from %v: pid %d exited with status %d", s.User(), s.RemoteAddr(), cmd.Process.Pid, code)
+	exitStatuses.Add(strconv.Itoa(code), 1)
+	sessionDuration.Observe(time.Since(au.Start).Seconds())
+	s.Exit(code)
+}
+
+// hostKey gives s its host key: the one in -hk, or a new one.
+// Serve would make one if there were none, but without a lock,
+// so with several listeners they would race, and might each
+// end up with a key of their own.
+func hostKey(s *ssh.Server) error {
+	if *hostKeyFile != "" {
+		if err := s.SetOption(ssh.HostKeyFile(*hostKeyFile)); err != nil {
+			return fmt.Errorf("host key %v: %v", *hostKeyFile, err)
+		}
+		return nil
+	}
+	key, err := rsa.GenerateKey(rand.Reader, 2048)
+	if err != nil {
+		return err
+	}
+	signer, err := ossh.NewSignerFromKey(key)
+	if err != nil {
+		return err
 	}
-	verbose("handler exits")
+	s.AddHostKey(signer)
+	return nil
 }
 
 func doInit() error {
@@ -523,18 +1247,16 @@ func doInit() error {
 	publicKeyOption := func(ctx ssh.Context, key ssh.PublicKey) bool {
 		// Glob the users's home directory for all the
 		// possible keys?
-		data, err := ioutil.ReadFile(*pubKeyFile)
-		if err != nil {
-			fmt.Print(err)
-			return false
+		id, ok := authorized(*pubKeyFile, key)
+		if ok {
+			ctx.SetValue(identityKey, id)
 		}
-		allowed, _, _, _, _ := ssh.ParseAuthorizedKey(data)
-		return ssh.KeysEqual(key, allowed)
+		return ok
 	}
 
 	// Now we run as an ssh server, and each time we get a connection,
 	// we run that command after setting things up for it.
-	forwardHandler := &ssh.ForwardedTCPHandler{}
+	forwardHandler := &rforward{}
 	server := ssh.Server{
 		LocalPortForwardingCallback: ssh.LocalPortForwardingCallback(func(ctx ssh.Context, dhost string, dport uint32) bool {
 			log.Println("Accepted forward", dhost, dport)
@@ -550,19 +1272,78 @@ func doInit() error {
 			"tcpip-forward":        forwardHandler.HandleSSHRequest,
 			"cancel-tcpip-forward": forwardHandler.HandleSSHRequest,
 		},
+		// direct-tcpip is what -L asks for.
+		ChannelHandlers: map[string]ssh.ChannelHandler{
+			"session":      ssh.DefaultSessionHandler,
+			"direct-tcpip": ssh.DirectTCPIPHandler,
+		},
 		Handler: handler,
 	}
 
+	if err := cgroupSetup(); err != nil {
+		return err
+	}
+	if *maxSessions > 0 {
+		slots = make(chan struct{}, *maxSessions)
+	}
+	// TCP keepalives only show the peer is there. These catch
+	// sessions that are wedged, or were just forgotten.
+	server.IdleTimeout, server.MaxTimeout = *idleTimeout, *maxDuration
+	server.ConnCallback = accepted
+	server.Version = versionString
+
 	// start the process reaper
 	procs := make(chan int)
 	go cpuDone(procs)
 
-	server.SetOption(ssh.HostKeyFile(*hostKeyFile))
-	log.Println("starting ssh server on port " + *port)
-	if err := server.ListenAndServe(); err != nil {
+	if err := hostKey(&server); err != nil {
+		return err
+	}
+	if *auditLog != "" {
+		if err := openAudit(*auditLog); err != nil {
+			return err
+		}
+	}
+	// listenAll picks up any health and metrics listeners
+	// -daemonize handed us, so it goes first.
+	ls, err := listenAll()
+	if err != nil {
+		return err
+	}
+	if *healthAddr != "" {
+		if err := health(*healthAddr); err != nil {
+			return err
+		}
+	}
+	if *metricsAddr != "" {
+		if err := metrics(*metricsAddr); err != nil {
+			return err
+		}
+	}
+	// As pid 1 we must never exit, so only a debug init gets
+	// to shut down on a signal.
+	done := make(chan struct{})
+	if os.Getpid() != 1 {
+		go shutdown(&server, done)
+	}
+	// The server is done when any listener is.
+	errc := make(chan error, len(ls))
+	for _, l := range ls {
+		log.Println("starting ssh server on " + l.Addr().Network() + " " + l.Addr().String())
+		go func(l net.Listener) {
+			errc <- server.Serve(l)
+		}(l)
+	}
+	atomic.StoreInt32(&serving, 1)
+	err = <-errc
+	atomic.StoreInt32(&serving, 0)
+	if err != nil {
 		log.Print(err)
+		if err == ssh.ErrServerClosed {
+			<-done
+		}
 	}
-	verbose("server.ListenAndServer returned")
+	verbose("server.Serve returned")
 
 	numprocs := <-procs
 	verbose("Reaped %d procs", numprocs)
@@ -581,28 +1362,58 @@ func usage() {
 func main() {
 	verbose("Args %v pid %d *runasinit %v *remote %v", os.Args, os.Getpid(), *runAsInit, *remote)
 	args := flag.Args()
+	// The server's sessions may choose our flags, but on a -runas
+	// or -allowcmd server they only get to be cpu -remote.
+	if (os.Getenv("CPU_RUNAS") != "" || os.Getenv("CPU_ALLOWCMD") != "") && (!*remote || *runAsInit || *checkOnly) {
+		log.Fatal("cpu: only cpu -remote runs on this server")
+	}
 	switch {
+	case *checkOnly:
+		if err := check(); err != nil {
+			log.Fatal(err)
+		}
 	case *runAsInit:
 		verbose("Running as Init")
+		// As pid 1 there is nobody to return to.
+		if *daemonize && os.Getpid() != 1 {
+			done, err := startDaemon()
+			if err != nil {
+				log.Fatal(err)
+			}
+			if done {
+				return
+			}
+		}
 		if err := doInit(); err != nil {
 			log.Fatal(err)
 		}
+		if *daemonize && *pidFile != "" {
+			os.Remove(*pidFile)
+		}
 	case *remote:
 		verbose("Running as remote")
 		if err := runRemote(strings.Join(flag.Args(), " "), *port9p); err != nil {
+			// The command ran and failed; pass its status on quietly.
+			if _, ok := err.(*exec.ExitError); ok {
+				os.Exit(exitCode(err))
+			}
 			log.Fatal(err)
 		}
 	default:
 		if len(args) == 0 {
 			usage()
 		}
-		host := args[0]
+		// JoinHostPort adds brackets to v6 hosts itself.
+		host := strings.TrimSuffix(strings.TrimPrefix(args[0], "["), "]")
 		a := strings.Join(args[1:], " ")
 		verbose("Running as client")
 		if a == "" {
 			a = os.Getenv("SHELL")
 		}
 		if err := runClient(host, a); err != nil {
+			if e, ok := err.(*ossh.ExitError); ok {
+				os.Exit(e.ExitStatus())
+			}
 			log.Fatal(err)
 		}
 	}
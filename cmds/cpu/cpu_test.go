@@ -0,0 +1,92 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"io/ioutil"
+	"os"
+	"path/filepath"
+	"reflect"
+	"testing"
+)
+
+// init in cpu.go parses the flags, and package variables are set
+// before any init runs, so this gets the test flags in first.
+var _ = func() bool {
+	testing.Init()
+	return true
+}()
+
+func TestRemoteArgs(t *testing.T) {
+	for _, tt := range []struct {
+		args []string
+		want []string
+		err  bool
+	}{
+		{
+			args: []string{"-remote", "-port9p", "40000", "-bin", "cpu", "date"},
+			want: []string{"-remote", "-port9p", "40000", "--", "date"},
+		},
+		{
+			args: []string{"-remote", "-port9p", "40000", "-bin", "cpu", "-cwd", "/home/x", "-ro", "-fd", "3</tmp/x", "ls", "-l"},
+			want: []string{"-remote", "-port9p", "40000", "-cwd", "/home/x", "-ro", "-fd", "3</tmp/x", "--", "ls", "-l"},
+		},
+		{
+			args: []string{"-remote", "-port9p", "40000", "--", "-x"},
+			want: []string{"-remote", "-port9p", "40000", "--", "-x"},
+		},
+		{args: []string{"-port9p", "40000", "date"}, err: true},
+		{args: []string{"-remote", "date"}, err: true},
+		{args: []string{"-remote", "-port9p", "x", "date"}, err: true},
+		{args: []string{"-remote", "-port9p", "40000", "-bindover", "/etc", "date"}, err: true},
+		{args: []string{"-remote", "-port9p", "40000", "-mountpoint", "/etc", "date"}, err: true},
+		{args: []string{"-remote", "-port9p", "40000", "-config", "/etc/shadow", "date"}, err: true},
+		{args: []string{"-remote", "-port9p", "40000", "-fd", "2</etc/shadow", "date"}, err: true},
+	} {
+		got, err := remoteArgs(tt.args)
+		if tt.err {
+			if err == nil {
+				t.Errorf("remoteArgs(%q): got %q, want error", tt.args, got)
+			}
+			continue
+		}
+		if err != nil {
+			t.Errorf("remoteArgs(%q): %v", tt.args, err)
+			continue
+		}
+		if !reflect.DeepEqual(got, tt.want) {
+			t.Errorf("remoteArgs(%q): got %q, want %q", tt.args, got, tt.want)
+		}
+	}
+}
+
+func TestWorkDir(t *testing.T) {
+	defer func(m string) {
+		*mntpt = m
+	}(*mntpt)
+	d, err := ioutil.TempDir("", "cpu")
+	if err != nil {
+		t.Fatal(err)
+	}
+	defer os.RemoveAll(d)
+	*mntpt = d
+	if err := os.MkdirAll(filepath.Join(d, "home/x"), 0755); err != nil {
+		t.Fatal(err)
+	}
+	for _, tt := range []struct {
+		dir, want string
+		err       bool
+	}{
+		{dir: ""},
+		{dir: "/home/x", want: filepath.Join(d, "home/x")},
+		{dir: d, want: d},
+		{dir: "/no/such/dir", err: true},
+	} {
+		got, err := workDir(tt.dir)
+		if (err != nil) != tt.err || got != tt.want {
+			t.Errorf("workDir(%q): got %q, %v, want %q, error %v", tt.dir, got, err, tt.want, tt.err)
+		}
+	}
+}
@@ -0,0 +1,201 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io/ioutil"
+	"log"
+	"log/syslog"
+	"net"
+	"os"
+	"os/exec"
+	"path/filepath"
+	"strconv"
+	"strings"
+	"syscall"
+)
+
+var (
+	daemonize = flag.Bool("daemonize", false, "run the server in the background once it is listening, logging to syslog")
+	pidFile   = flag.String("pidfile", "", "file -daemonize writes the background server's pid to")
+)
+
+// daemonEnv is set for the background server that -daemonize
+// starts. It lists the networks of the listeners it is handed,
+// from fd 3 on; the -healthaddr and -metricsaddr listeners are
+// listed as health and metrics.
+const daemonEnv = "CPU_DAEMON_NETS"
+
+// handed holds the health and metrics listeners background
+// handed us, by name.
+var handed = map[string]net.Listener{}
+
+// startDaemon does -daemonize. Go can't fork, so we start
+// ourselves again instead. In the server that was asked to
+// daemonize, it starts the background server and returns true:
+// we are done. In the background server, it sends the log to
+// syslog, moves to / and returns false.
+func startDaemon() (bool, error) {
+	if _, ok := os.LookupEnv(daemonEnv); !ok {
+		return true, background()
+	}
+	if w, err := syslog.New(syslog.LOG_DAEMON|syslog.LOG_INFO, "cpu"); err == nil {
+		log.SetFlags(0)
+		log.SetOutput(w)
+	}
+	// Don't keep the directory we were started in busy. The
+	// files our flags name have to be found from / now.
+	for _, p := range []*string{hostKeyFile, pubKeyFile, auditLog, pidFile} {
+		if *p == "" {
+			continue
+		}
+		var err error
+		if *p, err = filepath.Abs(*p); err != nil {
+			return false, err
+		}
+	}
+	if err := os.Chdir("/"); err != nil {
+		return false, err
+	}
+	return false, nil
+}
+
+// background starts the background server, detached from our
+// terminal, and hands it our listeners. Setting up the cgroups,
+// opening the audit log, listening and writing the pid file
+// happen here, so that errors go to whoever started us.
+func background() error {
+	if err := cgroupSetup(); err != nil {
+		return err
+	}
+	// The background server opens it again for itself.
+	if *auditLog != "" {
+		if err := openAudit(*auditLog); err != nil {
+			return err
+		}
+		audit.f.Close()
+		audit.f = nil
+	}
+	var ls []net.Listener
+	// -connect has nothing to listen on.
+	if *connect == "" {
+		var err error
+		if ls, err = listenAll(); err != nil {
+			return err
+		}
+	}
+	var (
+		nets  []string
+		files []*os.File
+	)
+	for _, l := range ls {
+		nets = append(nets, l.Addr().Network())
+	}
+	defer func() {
+		for _, l := range ls {
+			// The background server has the socket now.
+			if ul, ok := l.(*net.UnixListener); ok {
+				ul.SetUnlinkOnClose(false)
+			}
+			l.Close()
+		}
+		closeFds(files)
+	}()
+	for _, h := range []struct{ name, addr string }{{"health", *healthAddr}, {"metrics", *metricsAddr}} {
+		if h.addr == "" {
+			continue
+		}
+		l, err := listenHTTP(h.name, h.addr)
+		if err != nil {
+			return err
+		}
+		ls = append(ls, l)
+		nets = append(nets, h.name)
+	}
+	for _, l := range ls {
+		fl, ok := l.(interface {
+			File() (*os.File, error)
+		})
+		if !ok {
+			return fmt.Errorf("-daemonize: can't hand a %T to the background server", l)
+		}
+		f, err := fl.File()
+		if err != nil {
+			return err
+		}
+		files = append(files, f)
+	}
+	null, err := os.OpenFile(os.DevNull, os.O_RDWR, 0)
+	if err != nil {
+		return err
+	}
+	defer null.Close()
+	c := exec.Command("/proc/self/exe", os.Args[1:]...)
+	c.Args[0] = os.Args[0]
+	c.Env = append(os.Environ(), daemonEnv+"="+strings.Join(nets, ","))
+	c.Stdin, c.Stdout, c.Stderr = null, null, null
+	c.ExtraFiles = files
+	c.SysProcAttr = &syscall.SysProcAttr{Setsid: true}
+	if err := c.Start(); err != nil {
+		return err
+	}
+	if *pidFile != "" {
+		if err := ioutil.WriteFile(*pidFile, []byte(strconv.Itoa(c.Process.Pid)+"\n"), 0644); err != nil {
+			c.Process.Kill()
+			return fmt.Errorf("-pidfile: %v", err)
+		}
+	}
+	log.Printf("cpu server running in the background, pid %d", c.Process.Pid)
+	return nil
+}
+
+// inherited returns the listeners background handed us, or nil
+// if it handed us none.
+func inherited() ([]net.Listener, error) {
+	nets := os.Getenv(daemonEnv)
+	// Our children must not think they were handed listeners.
+	os.Unsetenv(daemonEnv)
+	if nets == "" {
+		return nil, nil
+	}
+	var ls []net.Listener
+	for i, n := range strings.Split(nets, ",") {
+		f := os.NewFile(uintptr(3+i), n)
+		var (
+			l   net.Listener
+			err error
+		)
+		if n == "vsock" {
+			l, err = vsockFileListener(f)
+		} else {
+			l, err = net.FileListener(f)
+		}
+		f.Close()
+		if err != nil {
+			return nil, fmt.Errorf("listener %d from -daemonize: %v", i, err)
+		}
+		if n == "health" || n == "metrics" {
+			handed[n] = l
+			continue
+		}
+		ls = append(ls, l)
+	}
+	return ls, nil
+}
+
+// listenHTTP returns the listener for the health or metrics
+// server: the one background handed us, or a new one on addr.
+func listenHTTP(name, addr string) (net.Listener, error) {
+	if l, ok := handed[name]; ok {
+		return l, nil
+	}
+	l, err := net.Listen("tcp", addr)
+	if err != nil {
+		return nil, fmt.Errorf("%v on %v: %v", name, addr, err)
+	}
+	return l, nil
+}
@@ -41,23 +41,171 @@
 //     it is running from outside the ssh session
 //
 // Options:
+//     -L value
+//           [bindaddr:]port:host:hostport: forward connections to port
+//           here to host:hostport as seen from the remote; may be
+//           repeated. As with ssh, bindaddr defaults to localhost.
+//     -R value
+//           [bindaddr:]port:host:hostport: forward connections to port
+//           on the remote to host:hostport as seen from here; may be
+//           repeated
+//     -addr string
+//           comma separated addresses for the server to listen on,
+//           each on -network or given as net!addr, e.g.
+//           unix!/run/cpu.sock,tcp!127.0.0.1:2222 (default :port, or
+//           /tmp/cpu.sock for unix; vsock is cid:port)
+//     -allowcmd string
+//           : separated absolute paths of the only commands the server
+//           runs, as found on the server. For cpu clients, include the
+//           cpu binary; but cpu -remote then runs the client's own
+//           binaries, through the mount point, and no path can
+//           restrict those. As with -runas, clients only choose the
+//           -cwd, -ro and -fd of cpu -remote (default any command)
+//     -allowuid string
+//           comma separated users or uids the server takes unix socket
+//           connections from, as the kernel reports them; ssh keys are
+//           still needed (default anyone who can connect)
+//     -auditlog string
+//           file the server appends JSON records of each session to:
+//           a "start" record when the command starts, and an "end"
+//           record when the session is over. Each has the user, key
+//           identity, remote address, argv, the cwd the client asked
+//           for, pid and start time; the end record adds the end time
+//           and exit status (default none)
+//     -backlog int
+//           listen backlog for the server's sockets. The kernel caps
+//           it at net.core.somaxconn, which is also the default, so
+//           raising it past that means raising the sysctl too.
 //     -bin string
 //           path of cpu binary
+//     -bindretries int
+//           how many more times the server tries to listen on an
+//           address before giving up
+//     -bindretrydelay duration
+//           how long the server waits before its first retry of a
+//           listen; the wait doubles each time (default 1s)
+//     -check
+//           check the server's flags, config file, keys and addresses,
+//           then exit, 0 if all is well and 1 with the first problem
+//     -config string
+//           file of flag settings, one name = value per line, with #
+//           comments; flags on the command line win
+//     -connect string
+//           address, on -network or as net!addr, the server dials to
+//           reach a client started with -reverse, instead of
+//           listening. It dials again when a connection ends.
+//     -cpumax string
+//           cgroup v2 cpu.max for each session, e.g. "50000 100000"
+//           for half a CPU (default no limit)
+//     -cwd string
+//           directory to run the remote command in, as seen from the
+//           client; the remote looks for it in the mount point, and
+//           then, with a warning, on the server (default the current
+//           directory)
 //     -d    enable debug prints
+//     -daemonize
+//           run the server in the background once it is listening,
+//           logging to syslog, in /. Errors setting up cgroups, the
+//           audit log and listeners still go to the shell.
 //     -dbg9p
 //           show 9p io
+//     -envallow string
+//           : separated patterns, as for filepath.Match, of the
+//           environment variables the server takes from clients.
+//           LD_PRELOAD, LD_AUDIT and LD_LIBRARY_PATH must be named
+//           outright; a pattern is not enough (default
+//           "TERM:COLUMNS:LINES:LANG:LANGUAGE:LC_*:TZ:PWD:HOME:PATH:USER:LOGNAME:SHELL")
+//     -envdeny string
+//           : separated patterns of environment variables the server
+//           drops, even if -envallow allows them
+//     -fd value
+//           n<file, n>file, n>>file or n<>file: open file, on the
+//           client, on fd n of the remote command, as the shell's
+//           redirections do. n must be 3 or more. The remote opens it
+//           through the mount point, as the user the command runs as,
+//           so it is the client's file; may be repeated
+//     -grace duration
+//           how long the server waits for sessions to end on SIGINT
+//           or SIGTERM, before it kills their process groups
+//           (default 5s)
+//     -healthaddr string
+//           TCP address for an HTTP health check of the server. GET
+//           /healthz says 200 and how many sessions are running while
+//           it is serving, and 503 otherwise (default none)
 //     -hostkey string
 //           host key file
+//     -idletimeout duration
+//           close client connections when no data has moved for this
+//           long (default 0, never)
+//     -keepalive duration
+//           how long a server connection is idle before TCP keepalive
+//           probes start; 0 turns keepalives off (default 15s)
+//     -keepalivecount int
+//           how many TCP keepalive probes go unanswered before a server
+//           connection is dropped (default the kernel's,
+//           net.ipv4.tcp_keepalive_probes)
+//     -keepaliveinterval duration
+//           time between TCP keepalive probes on server connections
+//           (default -keepalive)
 //     -key string
 //           key file (default "$HOME/.ssh/cpu_rsa")
+//     -maxconnsperminute int
+//           most connections the server takes from one peer in a
+//           minute; more are dropped before the ssh handshake
+//           (default 0, no limit)
+//     -maxduration duration
+//           close client connections this long after they connect
+//           (default 0, never)
+//     -maxmsize uint
+//           largest 9p msize the client's 9p server agrees to, from
+//           4096 to 4194304; a larger one asked for by the remote is
+//           cut down to this, in every Tversion, as are the counts of
+//           reads that would not fit in it (default 1048576)
+//     -maxsessions int
+//           most sessions the server runs at once; more are refused,
+//           or wait with -queue (default 0, no limit)
+//     -memorymax string
+//           cgroup v2 memory.max for each session, e.g. 1G (default
+//           no limit)
+//     -metricsaddr string
+//           TCP address for the server's metrics, in Prometheus text
+//           format on GET /metrics: connections, dropped connections,
+//           sessions started and running, exit statuses, 9P bytes to
+//           and from clients, and a histogram of session durations
+//           (default none)
+//     -mountpoint string
+//           where the remote mounts the client's files. It is made if
+//           it is under /tmp; anywhere else, it must already be a
+//           directory (default "/tmp/cpu")
 //     -network string
-//           network to use (default "tcp")
+//           network to use, tcp, tcp4, tcp6, unix or vsock (default "tcp")
 //     -p string
 //           port to use (default "22")
+//     -pidfile string
+//           file -daemonize writes the background server's pid to
+//     -ping duration
+//           how often the client checks, over ssh, that the server is
+//           still there; if it does not answer in that time, the
+//           client gives up (default 0, never)
 //     -port9p string
 //           port9p # on remote machine for 9p mount
+//     -queue
+//           when -maxsessions are running, make new sessions wait
+//           instead of refusing them
 //     -remote
 //           Indicates we are the remote side of the cpu session
+//     -reverse
+//           wait for a server started with -connect to dial host,
+//           instead of dialing it. -hk must name the server's host
+//           key, as nothing else tells us who dialed.
+//     -ro   mount the client's files read-only on the remote. The
+//           kernel enforces it, and the remote checks that it does.
+//     -runas string
+//           user the server runs the commands of cpu clients as, with
+//           that user's groups and no_new_privs set. Clients that run
+//           anything but cpu -remote are refused, and of its flags
+//           they only choose -cwd, -ro and -fd; the server sets the
+//           rest (default: leave them as they are)
 //     -srv string
 //           what server to run (default none; use internal)
 // Examples
@@ -0,0 +1,76 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"path/filepath"
+	"strings"
+)
+
+// defaultEnvAllow is what a command needs to look like it runs
+// where the client is: its terminal, locale, and where to find
+// its files and programs. Anything more must be asked for.
+const defaultEnvAllow = "TERM:COLUMNS:LINES:LANG:LANGUAGE:LC_*:TZ:PWD:HOME:PATH:USER:LOGNAME:SHELL"
+
+var (
+	envAllow = flag.String("envallow", defaultEnvAllow, ": separated patterns of environment variables the server takes from clients")
+	envDeny  = flag.String("envdeny", "", ": separated patterns of environment variables the server drops, even if allowed")
+)
+
+// serverEnv variables are set by the server, never by clients.
+var serverEnv = []string{"CPU_RUNAS", "CPU_ALLOWCMD", cgroupEnv}
+
+// dangerous variables are dropped unless -envallow names them
+// outright; a * is not enough.
+var dangerous = []string{"LD_PRELOAD", "LD_AUDIT", "LD_LIBRARY_PATH"}
+
+// filterEnv returns the variables in env, as sent by a client,
+// that the server passes on to the command.
+func filterEnv(env []string) []string {
+	var ok []string
+	for _, e := range env {
+		n := strings.SplitN(e, "=", 2)[0]
+		if !envAllowed(n) {
+			v("filterEnv: dropping %v", n)
+			continue
+		}
+		ok = append(ok, e)
+	}
+	return ok
+}
+
+func envAllowed(n string) bool {
+	for _, s := range serverEnv {
+		if n == s {
+			return false
+		}
+	}
+	if matchAny(*envDeny, n) {
+		return false
+	}
+	for _, d := range dangerous {
+		if n == d {
+			for _, a := range strings.Split(*envAllow, ":") {
+				if a == n {
+					return true
+				}
+			}
+			return false
+		}
+	}
+	return matchAny(*envAllow, n)
+}
+
+// matchAny reports whether n matches any of the : separated
+// patterns. Bad patterns match nothing.
+func matchAny(patterns, n string) bool {
+	for _, p := range strings.Split(patterns, ":") {
+		if ok, _ := filepath.Match(p, n); ok {
+			return true
+		}
+	}
+	return false
+}
@@ -0,0 +1,44 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import "testing"
+
+func TestEnvAllowed(t *testing.T) {
+	defer func(a, d string) {
+		*envAllow, *envDeny = a, d
+	}(*envAllow, *envDeny)
+	for _, tt := range []struct {
+		allow, deny string
+		n           string
+		want        bool
+	}{
+		{allow: "*", n: "TERM", want: true},
+		{allow: "*", n: "CPU_RUNAS"},
+		{allow: "*", n: cgroupEnv},
+		{allow: "CPU_ALLOWCMD", n: "CPU_ALLOWCMD"},
+		{allow: "*", n: "LD_PRELOAD"},
+		{allow: "LD_*", n: "LD_PRELOAD"},
+		{allow: "*:LD_PRELOAD", n: "LD_PRELOAD", want: true},
+		{allow: "*", deny: "SSH_*", n: "SSH_AUTH_SOCK"},
+		{allow: "TERM:LC_*", n: "LC_ALL", want: true},
+		{allow: "TERM:LC_*", n: "HOME"},
+		{allow: "LD_PRELOAD", deny: "LD_*", n: "LD_PRELOAD"},
+		{allow: "[", n: "["},
+		{allow: "", n: "TERM"},
+		{allow: defaultEnvAllow, n: "TERM", want: true},
+		{allow: defaultEnvAllow, n: "LC_CTYPE", want: true},
+		{allow: defaultEnvAllow, n: "PWD", want: true},
+		{allow: defaultEnvAllow, n: "SSH_AUTH_SOCK"},
+		{allow: defaultEnvAllow, n: "LD_LIBRARY_PATH"},
+		{allow: "*", n: "LD_LIBRARY_PATH"},
+		{allow: "*:LD_LIBRARY_PATH", n: "LD_LIBRARY_PATH", want: true},
+	} {
+		*envAllow, *envDeny = tt.allow, tt.deny
+		if got := envAllowed(tt.n); got != tt.want {
+			t.Errorf("envAllowed(%q) with -envallow %q -envdeny %q: got %v, want %v", tt.n, tt.allow, tt.deny, got, tt.want)
+		}
+	}
+}
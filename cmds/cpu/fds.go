@@ -0,0 +1,181 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"os"
+	"path/filepath"
+	"runtime"
+	"strconv"
+	"strings"
+	"syscall"
+
+	"golang.org/x/sys/unix"
+)
+
+// fdFiles is a repeatable flag of n<file and the like, asking
+// for file to be open on fd n of the remote command.
+type fdFiles []string
+
+func (f *fdFiles) String() string {
+	return strings.Join(*f, ",")
+}
+
+func (f *fdFiles) Set(s string) error {
+	if _, err := parseFd(s); err != nil {
+		return err
+	}
+	*f = append(*f, s)
+	return nil
+}
+
+var extraFds = func() *fdFiles {
+	f := &fdFiles{}
+	flag.Var(f, "fd", "n<file, n>file, n>>file or n<>file: open file here on fd n, 3 or more, of the remote command, as the shell's redirections do; may be repeated")
+	return f
+}()
+
+// fdModes are the ways -fd opens files, as the shell's
+// redirections do. The longer ops come first, for parseFd.
+var fdModes = []struct {
+	op    string
+	flags int
+}{
+	{"<>", os.O_RDWR | os.O_CREATE},
+	{">>", os.O_WRONLY | os.O_CREATE | os.O_APPEND},
+	{"<", os.O_RDONLY},
+	{">", os.O_WRONLY | os.O_CREATE | os.O_TRUNC},
+}
+
+// fdSpec is a parsed -fd value.
+type fdSpec struct {
+	n     int
+	op    string
+	flags int
+	file  string
+}
+
+// parseFd parses an -fd value.
+func parseFd(s string) (fdSpec, error) {
+	i := strings.IndexAny(s, "<>")
+	if i < 0 {
+		return fdSpec{}, fmt.Errorf("%q is not n<file, n>file, n>>file or n<>file", s)
+	}
+	n, err := strconv.Atoi(s[:i])
+	if err != nil {
+		return fdSpec{}, fmt.Errorf("%q: bad fd: %v", s, err)
+	}
+	// exec gives the command stdio on 0, 1 and 2 anyway.
+	if n < 3 {
+		return fdSpec{}, fmt.Errorf("-fd %d: 0, 1 and 2 are stdin, stdout and stderr", n)
+	}
+	for _, m := range fdModes {
+		if f := s[i:]; strings.HasPrefix(f, m.op) {
+			if f = f[len(m.op):]; f == "" {
+				break
+			}
+			return fdSpec{n: n, op: m.op, flags: m.flags, file: f}, nil
+		}
+	}
+	return fdSpec{}, fmt.Errorf("%q is not n<file, n>file, n>>file or n<>file", s)
+}
+
+// fdArgs returns the -fd flags for the remote. Files are named
+// as absolute paths here, which the remote finds in the mount point.
+func fdArgs() (string, error) {
+	var a string
+	for _, s := range *extraFds {
+		d, _ := parseFd(s)
+		p, err := filepath.Abs(d.file)
+		if err != nil {
+			return "", err
+		}
+		a += fmt.Sprintf(" -fd %q", fmt.Sprintf("%d%v%v", d.n, d.op, p))
+	}
+	return a, nil
+}
+
+// openFds opens the -fd files through the 9p mount, as cred if
+// it is not nil, and returns them as exec.Cmd.ExtraFiles would
+// have them: fd n is at n-3, and gaps are nil, so they are closed
+// in the command.
+func openFds(cred *syscall.Credential) ([]*os.File, error) {
+	type result struct {
+		files []*os.File
+		err   error
+	}
+	c := make(chan result)
+	go func() {
+		// File system ids are per thread. This one is never
+		// unlocked, so it goes away, with its ids, when we are
+		// done with it.
+		runtime.LockOSThread()
+		if cred != nil {
+			if err := setfsids(cred); err != nil {
+				c <- result{err: err}
+				return
+			}
+		}
+		files, err := openFdsHere()
+		c <- result{files: files, err: err}
+	}()
+	r := <-c
+	return r.files, r.err
+}
+
+// setfsids makes the file system ids and groups of this thread
+// those of cred, so files are opened as the command would open
+// them. setgroups is a raw system call here, so it is per thread
+// too.
+func setfsids(cred *syscall.Credential) error {
+	g := make([]int, len(cred.Groups))
+	for i, id := range cred.Groups {
+		g[i] = int(id)
+	}
+	if err := unix.Setgroups(g); err != nil {
+		return err
+	}
+	if err := unix.Setfsgid(int(cred.Gid)); err != nil {
+		return err
+	}
+	return unix.Setfsuid(int(cred.Uid))
+}
+
+func openFdsHere() ([]*os.File, error) {
+	var files []*os.File
+	for _, s := range *extraFds {
+		d, _ := parseFd(s)
+		// The client names files on its side, so they must
+		// stay in the mount point.
+		p := filepath.Join(*mntpt, d.file)
+		if r, err := filepath.Rel(*mntpt, p); err != nil || r == ".." || strings.HasPrefix(r, "../") {
+			closeFds(files)
+			return nil, fmt.Errorf("-fd %v: %v is not in %v", s, d.file, *mntpt)
+		}
+		o, err := os.OpenFile(p, d.flags, 0644)
+		if err != nil {
+			closeFds(files)
+			return nil, fmt.Errorf("-fd %v: %v", s, err)
+		}
+		for len(files) <= d.n-3 {
+			files = append(files, nil)
+		}
+		if files[d.n-3] != nil {
+			files[d.n-3].Close()
+		}
+		files[d.n-3] = o
+	}
+	return files, nil
+}
+
+func closeFds(files []*os.File) {
+	for _, f := range files {
+		if f != nil {
+			f.Close()
+		}
+	}
+}
@@ -0,0 +1,98 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"io/ioutil"
+	"os"
+	"path/filepath"
+	"testing"
+)
+
+func TestParseFd(t *testing.T) {
+	for _, tt := range []struct {
+		s    string
+		want fdSpec
+		err  bool
+	}{
+		{s: "3</tmp/x", want: fdSpec{3, "<", os.O_RDONLY, "/tmp/x"}},
+		{s: "10>log", want: fdSpec{10, ">", os.O_WRONLY | os.O_CREATE | os.O_TRUNC, "log"}},
+		{s: "4>>log", want: fdSpec{4, ">>", os.O_WRONLY | os.O_CREATE | os.O_APPEND, "log"}},
+		{s: "5<>a=b", want: fdSpec{5, "<>", os.O_RDWR | os.O_CREATE, "a=b"}},
+		{s: "3<<x", want: fdSpec{3, "<", os.O_RDONLY, "<x"}},
+		{s: "2</tmp/x", err: true},
+		{s: "0>/tmp/x", err: true},
+		{s: "-1</tmp/x", err: true},
+		{s: "3<", err: true},
+		{s: "3<>", err: true},
+		{s: "3", err: true},
+		{s: "3=/tmp/x", err: true},
+		{s: "x</tmp/x", err: true},
+		{s: "</tmp/x", err: true},
+	} {
+		got, err := parseFd(tt.s)
+		if tt.err {
+			if err == nil {
+				t.Errorf("parseFd(%q): got %+v, want error", tt.s, got)
+			}
+			continue
+		}
+		if err != nil {
+			t.Errorf("parseFd(%q): %v", tt.s, err)
+			continue
+		}
+		if got != tt.want {
+			t.Errorf("parseFd(%q): got %+v, want %+v", tt.s, got, tt.want)
+		}
+	}
+}
+
+func TestOpenFds(t *testing.T) {
+	defer func(m string, f fdFiles) {
+		*mntpt, *extraFds = m, f
+	}(*mntpt, *extraFds)
+	d, err := ioutil.TempDir("", "cpu")
+	if err != nil {
+		t.Fatal(err)
+	}
+	defer os.RemoveAll(d)
+	*mntpt = d
+	if err := ioutil.WriteFile(filepath.Join(d, "in"), []byte("in"), 0644); err != nil {
+		t.Fatal(err)
+	}
+	for _, tt := range []struct {
+		name string
+		fds  fdFiles
+		err  bool
+	}{
+		{name: "read", fds: fdFiles{"3</in"}},
+		{name: "write", fds: fdFiles{"4>/out"}},
+		{name: "gap", fds: fdFiles{"3</in", "5>>/out"}},
+		{name: "missing", fds: fdFiles{"3</nothere"}, err: true},
+		{name: "escape", fds: fdFiles{"3</../" + filepath.Base(d) + "x"}, err: true},
+		{name: "dotdot", fds: fdFiles{"3</../../etc/passwd"}, err: true},
+	} {
+		t.Run(tt.name, func(t *testing.T) {
+			*extraFds = tt.fds
+			files, err := openFds(nil)
+			defer closeFds(files)
+			if tt.err {
+				if err == nil {
+					t.Fatalf("openFds: got nil, want error")
+				}
+				return
+			}
+			if err != nil {
+				t.Fatalf("openFds: %v", err)
+			}
+			for _, s := range tt.fds {
+				fd, _ := parseFd(s)
+				if len(files) <= fd.n-3 || files[fd.n-3] == nil {
+					t.Errorf("openFds: no file for %v in %v", s, files)
+				}
+			}
+		})
+	}
+}
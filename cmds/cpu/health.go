@@ -0,0 +1,37 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"net/http"
+	"sync/atomic"
+)
+
+var healthAddr = flag.String("healthaddr", "", "TCP address for an HTTP health check of the server, GET /healthz (default none)")
+
+// serving is 1 while the server is accepting on all its listeners.
+var serving int32
+
+// health starts the health check server on addr. /healthz says
+// 200 and how many sessions are running while we are serving,
+// and 503 before we start and once we stop.
+func health(addr string) error {
+	mux := http.NewServeMux()
+	mux.HandleFunc("/healthz", func(w http.ResponseWriter, r *http.Request) {
+		if atomic.LoadInt32(&serving) == 0 {
+			http.Error(w, "not serving", http.StatusServiceUnavailable)
+			return
+		}
+		fmt.Fprintf(w, "ok %d sessions\n", numSessions())
+	})
+	l, err := listenHTTP("health", addr)
+	if err != nil {
+		return err
+	}
+	go http.Serve(l, mux)
+	return nil
+}
@@ -16,11 +16,14 @@ package main
 
 import (
 	"flag"
+	"fmt"
 	"log"
 	"os/exec"
 	"syscall"
+	"unsafe"
 
 	"github.com/u-root/u-root/pkg/uroot/util"
+	"golang.org/x/sys/unix"
 )
 
 var (
@@ -35,21 +38,55 @@ func cpuSetup() error {
 	osInitGo()
 	// TODO: this needs to be added as prt of the Rootfs() stuff
 	if o, err := exec.Command("ip", "link", "set", "dev", "lo", "up").CombinedOutput(); err != nil {
-		log.Fatalf("ip link set dev lo: %v (%v)", string(o), err)
+		return fmt.Errorf("ip link set dev lo: %v (%v)", string(o), err)
 	}
 	return nil
 }
 
+// exited waits for a child to exit and returns its pid, leaving
+// it unreaped, so that whoever started it can still wait for it.
+// There is no waitid in syscall or x/sys, so it is done by hand.
+func exited() (int, error) {
+	const pAll = 0
+	var info [128]byte
+	for {
+		_, _, e := syscall.Syscall6(unix.SYS_WAITID, pAll, 0, uintptr(unsafe.Pointer(&info[0])), unix.WEXITED|unix.WNOWAIT, 0, 0)
+		if e == syscall.EINTR {
+			continue
+		}
+		if e != 0 {
+			return -1, e
+		}
+		break
+	}
+	// si_pid follows si_signo, si_errno and si_code, in a union
+	// which is pointer aligned.
+	off := 12
+	if unsafe.Sizeof(uintptr(0)) == 8 {
+		off = 16
+	}
+	return int(*(*int32)(unsafe.Pointer(&info[off]))), nil
+}
+
 func cpuDone(c chan int) {
-	// We need to reap all children before exiting.
+	// We need to reap all children before exiting. Those that
+	// are sessions' are left to the session's cmd.Wait, or it
+	// would lose the exit status.
 	var procs int
 	log.Printf("init: Waiting for orphaned children")
 	for {
+		pid, err := exited()
+		if err != nil {
+			break
+		}
+		if waitSession(pid) {
+			continue
+		}
 		var s syscall.WaitStatus
 		var r syscall.Rusage
-		p, err := syscall.Wait4(-1, &s, 0, &r)
+		p, err := syscall.Wait4(pid, &s, 0, &r)
 		if p == -1 {
-			break
+			continue
 		}
 		log.Printf("%v: exited with %v, status %v, rusage %v", p, err, s, r)
 		procs++
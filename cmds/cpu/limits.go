@@ -0,0 +1,142 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+// Limits on what clients of the cpu server can use.
+package main
+
+import (
+	"container/list"
+	"context"
+	"flag"
+	"fmt"
+	"math"
+	"os/exec"
+	"path/filepath"
+	"strings"
+	"sync"
+	"time"
+)
+
+var (
+	maxSessions = flag.Int("maxsessions", 0, "most sessions the server runs at once (0 for no limit)")
+	queue       = flag.Bool("queue", false, "when -maxsessions are running, make new sessions wait instead of refusing them")
+	idleTimeout = flag.Duration("idletimeout", 0, "close client connections when no data has moved for this long (0 for never)")
+	maxDuration = flag.Duration("maxduration", 0, "close client connections this long after they connect (0 for never)")
+	allowCmd    = flag.String("allowcmd", "", ": separated paths of the only commands the server runs; for cpu clients, include the cpu binary, but cpu -remote then runs the client's own binaries, and no path can restrict those")
+	maxConnRate = flag.Int("maxconnsperminute", 0, "most connections the server takes from one peer in a minute; more are dropped before the ssh handshake (0 for no limit)")
+
+	// slots holds a token for each running session.
+	// It is nil if there is no limit.
+	slots chan struct{}
+)
+
+// startSession takes a session slot, waiting for one if -queue
+// is set and failing if not. The returned func gives it back.
+func startSession(ctx context.Context) (func(), error) {
+	if slots == nil {
+		return func() {}, nil
+	}
+	release := func() { <-slots }
+	if *queue {
+		select {
+		case slots <- struct{}{}:
+			return release, nil
+		case <-ctx.Done():
+			return nil, ctx.Err()
+		}
+	}
+	select {
+	case slots <- struct{}{}:
+		return release, nil
+	default:
+		return nil, fmt.Errorf("server busy: %d sessions already running", cap(slots))
+	}
+}
+
+// resolve returns the absolute path of command n, as exec would
+// find it. Symlinks are deliberately left alone: in a busybox,
+// every command is a link to the same binary.
+func resolve(n string) (string, error) {
+	p, err := exec.LookPath(n)
+	if err != nil {
+		return "", err
+	}
+	return filepath.Abs(p)
+}
+
+// cmdAllowed reports whether the command n, looked up on the
+// server, is in the : separated list of paths, and returns its
+// path. An empty list allows everything.
+func cmdAllowed(list, n string) (string, error) {
+	p, err := resolve(n)
+	if err != nil {
+		return "", err
+	}
+	if list == "" {
+		return p, nil
+	}
+	for _, a := range strings.Split(list, ":") {
+		if a == p {
+			return p, nil
+		}
+	}
+	return "", fmt.Errorf("%v: command not allowed", p)
+}
+
+// maxBuckets is the most peers rateOK keeps a bucket for.
+const maxBuckets = 4096
+
+// buckets holds a token bucket per peer for -maxconnsperminute,
+// the most recently used at the front of lru.
+var buckets = struct {
+	sync.Mutex
+	m   map[string]*list.Element
+	lru *list.List
+}{m: map[string]*list.Element{}, lru: list.New()}
+
+type bucket struct {
+	peer   string
+	tokens float64
+	last   time.Time
+}
+
+// rateOK takes a token from the bucket for peer, and reports
+// whether there was one. A bucket holds a minute's worth of
+// tokens, so a peer can use them all at once, and refills at
+// -maxconnsperminute.
+func rateOK(peer string, now time.Time) bool {
+	if *maxConnRate <= 0 {
+		return true
+	}
+	buckets.Lock()
+	defer buckets.Unlock()
+	max := float64(*maxConnRate)
+	var b *bucket
+	if e, ok := buckets.m[peer]; ok {
+		buckets.lru.MoveToFront(e)
+		b = e.Value.(*bucket)
+	} else {
+		b = &bucket{peer: peer, tokens: max, last: now}
+		buckets.m[peer] = buckets.lru.PushFront(b)
+	}
+	b.tokens = math.Min(max, b.tokens+now.Sub(b.last).Minutes()*max)
+	b.last = now
+	// Buckets untouched for a minute are full again, and we can
+	// forget them; they are at the back. Scanners come from many
+	// addresses, so past maxBuckets the least recently used go
+	// too, though that gives them a full bucket if they return.
+	for e := buckets.lru.Back(); e != buckets.lru.Front(); e = buckets.lru.Back() {
+		old := e.Value.(*bucket)
+		if buckets.lru.Len() <= maxBuckets && now.Sub(old.last) <= time.Minute {
+			break
+		}
+		buckets.lru.Remove(e)
+		delete(buckets.m, old.peer)
+	}
+	if b.tokens < 1 {
+		return false
+	}
+	b.tokens--
+	return true
+}
@@ -0,0 +1,96 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"container/list"
+	"fmt"
+	"testing"
+	"time"
+)
+
+// rateStep is a connection from peer, at a time after the start,
+// and whether rateOK should let it in.
+type rateStep struct {
+	peer string
+	at   time.Duration
+	want bool
+}
+
+func resetBuckets() {
+	buckets.m, buckets.lru = map[string]*list.Element{}, list.New()
+}
+
+func TestRateOK(t *testing.T) {
+	defer func(r int) {
+		*maxConnRate = r
+	}(*maxConnRate)
+	start := time.Date(2019, 5, 17, 16, 53, 0, 0, time.UTC)
+	for _, tt := range []struct {
+		name  string
+		rate  int
+		steps []rateStep
+	}{
+		{
+			name: "no limit",
+			steps: []rateStep{
+				{"a", 0, true},
+				{"a", 0, true},
+				{"a", 0, true},
+			},
+		},
+		{
+			name: "two a minute",
+			rate: 2,
+			steps: []rateStep{
+				{"a", 0, true},
+				{"a", 0, true},
+				{"a", 0, false},
+				{"b", 0, true},
+				{"a", 30 * time.Second, true},
+				{"a", 30 * time.Second, false},
+				{"a", 45 * time.Second, false},
+				{"a", 75 * time.Second, true},
+				{"a", 5 * time.Minute, true},
+				{"a", 5 * time.Minute, true},
+				{"a", 5 * time.Minute, false},
+				{"b", 5 * time.Minute, true},
+			},
+		},
+	} {
+		t.Run(tt.name, func(t *testing.T) {
+			*maxConnRate = tt.rate
+			resetBuckets()
+			for i, s := range tt.steps {
+				if got := rateOK(s.peer, start.Add(s.at)); got != s.want {
+					t.Errorf("step %d: rateOK(%q, +%v): got %v, want %v", i, s.peer, s.at, got, s.want)
+				}
+			}
+		})
+	}
+}
+
+func TestRateOKForgets(t *testing.T) {
+	defer func(r int) {
+		*maxConnRate = r
+	}(*maxConnRate)
+	defer resetBuckets()
+	*maxConnRate = 1
+	resetBuckets()
+	start := time.Date(2019, 5, 17, 16, 53, 0, 0, time.UTC)
+	for i := 0; i < 2*maxBuckets; i++ {
+		rateOK(fmt.Sprint(i), start)
+	}
+	if n := len(buckets.m); n != maxBuckets {
+		t.Errorf("after %d peers: got %d buckets, want %d", 2*maxBuckets, n, maxBuckets)
+	}
+	if rateOK(fmt.Sprint(2*maxBuckets-1), start) {
+		t.Errorf("the last peer got a second connection in a minute")
+	}
+	rateOK("new", start.Add(2*time.Minute))
+	if n := len(buckets.m); n != 1 {
+		t.Errorf("two minutes on: got %d buckets, want 1", n)
+	}
+}
@@ -0,0 +1,184 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io"
+	"net"
+	"net/http"
+	"sort"
+	"sync"
+	"sync/atomic"
+)
+
+var metricsAddr = flag.String("metricsaddr", "", "TCP address for the server's metrics, in Prometheus text format on GET /metrics (default none)")
+
+// Server metrics, on /metrics of the -metricsaddr server. There is
+// no Prometheus client in vendor, but the text format is simple
+// enough to write by hand.
+var (
+	connections     = newLabelled("cpu_connections_total", "Connections accepted, by network.", "network")
+	droppedConns    = newCounter("cpu_connections_dropped_total", "Connections dropped before the ssh handshake.")
+	sessionCount    = newCounter("cpu_sessions_total", "Sessions whose command was started.")
+	exitStatuses    = newLabelled("cpu_exit_status_total", "Sessions ended, by exit status.", "status")
+	ninePBytes      = newLabelled("cpu_9p_bytes_total", "Bytes of 9P forwarded for sessions, in from or out to the clients.", "direction")
+	sessionDuration = newHistogram("cpu_session_duration_seconds", "How long sessions whose command was started took.", []float64{1, 10, 60, 300, 900, 3600, 4 * 3600, 24 * 3600})
+)
+
+// metric is anything that writes itself in the text format.
+type metric interface {
+	write(w io.Writer)
+}
+
+// allMetrics is what /metrics shows, in order.
+var allMetrics = []metric{
+	connections,
+	droppedConns,
+	sessionCount,
+	gauge{"cpu_sessions_running", "Sessions whose command is running.", numSessions},
+	exitStatuses,
+	ninePBytes,
+	sessionDuration,
+}
+
+func header(w io.Writer, name, help, kind string) {
+	fmt.Fprintf(w, "# HELP %s %s\n# TYPE %s %s\n", name, help, name, kind)
+}
+
+// counter is a counter with no labels.
+type counter struct {
+	name, help string
+	n          int64
+}
+
+func newCounter(name, help string) *counter {
+	return &counter{name: name, help: help}
+}
+
+func (c *counter) Add(n int64) {
+	atomic.AddInt64(&c.n, n)
+}
+
+func (c *counter) write(w io.Writer) {
+	header(w, c.name, c.help, "counter")
+	fmt.Fprintf(w, "%s %d\n", c.name, atomic.LoadInt64(&c.n))
+}
+
+// labelled is a counter with one label.
+type labelled struct {
+	sync.Mutex
+	name, help, label string
+	n                 map[string]int64
+}
+
+func newLabelled(name, help, label string) *labelled {
+	return &labelled{name: name, help: help, label: label, n: map[string]int64{}}
+}
+
+func (c *labelled) Add(v string, n int64) {
+	c.Lock()
+	c.n[v] += n
+	c.Unlock()
+}
+
+func (c *labelled) write(w io.Writer) {
+	c.Lock()
+	defer c.Unlock()
+	header(w, c.name, c.help, "counter")
+	var vs []string
+	for v := range c.n {
+		vs = append(vs, v)
+	}
+	sort.Strings(vs)
+	for _, v := range vs {
+		fmt.Fprintf(w, "%s{%s=%q} %d\n", c.name, c.label, v, c.n[v])
+	}
+}
+
+// gauge is a value read when it is shown.
+type gauge struct {
+	name, help string
+	f          func() int
+}
+
+func (g gauge) write(w io.Writer) {
+	header(w, g.name, g.help, "gauge")
+	fmt.Fprintf(w, "%s %d\n", g.name, g.f())
+}
+
+// histogram counts observations into buckets by upper bound.
+// n has one more count than bounds, for +Inf.
+type histogram struct {
+	sync.Mutex
+	name, help string
+	bounds     []float64
+	n          []uint64
+	sum        float64
+}
+
+func newHistogram(name, help string, bounds []float64) *histogram {
+	return &histogram{name: name, help: help, bounds: bounds, n: make([]uint64, len(bounds)+1)}
+}
+
+func (h *histogram) Observe(v float64) {
+	h.Lock()
+	defer h.Unlock()
+	h.n[sort.SearchFloat64s(h.bounds, v)]++
+	h.sum += v
+}
+
+// write writes the buckets cumulatively, as Prometheus wants them.
+func (h *histogram) write(w io.Writer) {
+	h.Lock()
+	defer h.Unlock()
+	header(w, h.name, h.help, "histogram")
+	var n uint64
+	for i, b := range h.bounds {
+		n += h.n[i]
+		fmt.Fprintf(w, "%s_bucket{le=\"%g\"} %d\n", h.name, b, n)
+	}
+	n += h.n[len(h.bounds)]
+	fmt.Fprintf(w, "%s_bucket{le=\"+Inf\"} %d\n", h.name, n)
+	fmt.Fprintf(w, "%s_sum %g\n", h.name, h.sum)
+	fmt.Fprintf(w, "%s_count %d\n", h.name, n)
+}
+
+// metrics starts the server for the metrics on addr.
+func metrics(addr string) error {
+	mux := http.NewServeMux()
+	mux.HandleFunc("/metrics", func(w http.ResponseWriter, r *http.Request) {
+		w.Header().Set("Content-Type", "text/plain; version=0.0.4")
+		for _, m := range allMetrics {
+			m.write(w)
+		}
+	})
+	l, err := listenHTTP("metrics", addr)
+	if err != nil {
+		return err
+	}
+	go http.Serve(l, mux)
+	return nil
+}
+
+// countConn counts the bytes through the remote's end of a
+// session's 9P connection, in ninePBytes: what it reads goes out
+// to the client, and what it writes came in from it.
+type countConn struct {
+	net.Conn
+}
+
+func (c *countConn) Read(b []byte) (int, error) {
+	n, err := c.Conn.Read(b)
+	ninePBytes.Add("out", int64(n))
+	return n, err
+}
+
+func (c *countConn) Write(b []byte) (int, error) {
+	n, err := c.Conn.Write(b)
+	ninePBytes.Add("in", int64(n))
+	return n, err
+}
@@ -0,0 +1,40 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"bytes"
+	"testing"
+)
+
+func TestMetricsWrite(t *testing.T) {
+	c := newCounter("c_total", "A counter.")
+	c.Add(2)
+	l := newLabelled("l_total", "A labelled counter.", "k")
+	l.Add("b", 1)
+	l.Add("a", 3)
+	l.Add("b", 1)
+	h := newHistogram("h_seconds", "A histogram.", []float64{1, 10})
+	for _, v := range []float64{0.5, 1, 5, 20} {
+		h.Observe(v)
+	}
+	for _, tt := range []struct {
+		m    metric
+		want string
+	}{
+		{c, "# HELP c_total A counter.\n# TYPE c_total counter\nc_total 2\n"},
+		{l, "# HELP l_total A labelled counter.\n# TYPE l_total counter\nl_total{k=\"a\"} 3\nl_total{k=\"b\"} 2\n"},
+		{gauge{"g", "A gauge.", func() int { return 4 }}, "# HELP g A gauge.\n# TYPE g gauge\ng 4\n"},
+		{h, "# HELP h_seconds A histogram.\n# TYPE h_seconds histogram\n" +
+			"h_seconds_bucket{le=\"1\"} 2\nh_seconds_bucket{le=\"10\"} 3\nh_seconds_bucket{le=\"+Inf\"} 4\n" +
+			"h_seconds_sum 26.5\nh_seconds_count 4\n"},
+	} {
+		var b bytes.Buffer
+		tt.m.write(&b)
+		if got := b.String(); got != tt.want {
+			t.Errorf("write: got\n%s\nwant\n%s", got, tt.want)
+		}
+	}
+}
@@ -0,0 +1,148 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"encoding/binary"
+	"flag"
+	"fmt"
+	"io"
+	"net"
+)
+
+var maxMsize = flag.Uint("maxmsize", 1<<20, "largest 9p msize our 9p server agrees to; a larger one in the remote's Tversion is cut down to this, as are reads that would not fit")
+
+// 9p message types that msizeConn looks into.
+const (
+	treaddir = 40
+	tversion = 100
+	tread    = 116
+)
+
+const (
+	// The p9 server refuses an msize over 4M, and nothing
+	// useful fits in less than a page.
+	minMsize = 4096
+	p9Msize  = 4 << 20
+
+	// hdrSize is the size[4] type[1] tag[2] that starts every
+	// 9p message, and ioHdrSize the count[4] after it in the
+	// Rread and Rreaddir that carry data.
+	hdrSize   = 7
+	ioHdrSize = hdrSize + 4
+)
+
+// checkMsize checks -maxmsize.
+func checkMsize() error {
+	if *maxMsize < minMsize || *maxMsize > p9Msize {
+		return fmt.Errorf("-maxmsize %d: must be from %d to %d", *maxMsize, minMsize, p9Msize)
+	}
+	return nil
+}
+
+// msizeListener clamps the msize of the 9p connections it
+// accepts to -maxmsize.
+type msizeListener struct {
+	net.Listener
+}
+
+// Accept implements net.Listener.Accept.
+func (l msizeListener) Accept() (net.Conn, error) {
+	c, err := l.Listener.Accept()
+	if err != nil {
+		return nil, err
+	}
+	return &msizeConn{Conn: c, max: uint32(*maxMsize)}, nil
+}
+
+// msizeConn keeps the 9p connection it reads from to -maxmsize.
+// It cuts the msize in every Tversion down to max; the p9 server
+// answers with the msize it was sent, so the client learns the
+// smaller one, and the server refuses any bigger message before
+// it reads it. It cuts the count of each Tread and Treaddir down
+// to what fits in the agreed msize too, since the p9 server
+// makes a buffer of whatever count it is asked for. The rest of
+// each message passes through untouched.
+type msizeConn struct {
+	net.Conn
+	max   uint32
+	msize uint32 // agreed by the last Tversion, or max before one
+	hdr   []byte // start of the message, as we have it, still to be read
+	left  uint32 // bytes of the message after hdr still to be read
+}
+
+func (c *msizeConn) Read(b []byte) (int, error) {
+	if len(c.hdr) == 0 && c.left == 0 {
+		if err := c.next(); err != nil {
+			return 0, err
+		}
+	}
+	if len(c.hdr) > 0 {
+		n := copy(b, c.hdr)
+		c.hdr = c.hdr[n:]
+		return n, nil
+	}
+	if uint32(len(b)) > c.left {
+		b = b[:c.left]
+	}
+	n, err := c.Conn.Read(b)
+	c.left -= uint32(n)
+	if err == io.EOF && c.left > 0 {
+		err = io.ErrUnexpectedEOF
+	}
+	return n, err
+}
+
+// next reads the start of the next message, as far as the field
+// we might change, and changes it.
+func (c *msizeConn) next() error {
+	if c.msize == 0 {
+		c.msize = c.max
+	}
+	h := make([]byte, hdrSize, 23)
+	if _, err := io.ReadFull(c.Conn, h); err != nil {
+		return err
+	}
+	size := binary.LittleEndian.Uint32(h)
+	if size < hdrSize {
+		return fmt.Errorf("9p: message of %d bytes is too short", size)
+	}
+	// Tversion is tag[2] msize[4] version[s]; Tread and
+	// Treaddir are tag[2] fid[4] offset[8] count[4].
+	var n, at uint32
+	switch h[4] {
+	case tversion:
+		n, at = 11, 7
+	case tread, treaddir:
+		n, at = 23, 19
+	}
+	// A message too short for the field is the server's to
+	// refuse; pass it on as it is.
+	if n > size {
+		n = 0
+	}
+	if n > hdrSize {
+		h = h[:n]
+		if _, err := io.ReadFull(c.Conn, h[hdrSize:]); err != nil {
+			return err
+		}
+		m := binary.LittleEndian.Uint32(h[at:])
+		if h[4] == tversion {
+			if m > c.max {
+				v("9p: msize %d asked for, %d allowed", m, c.max)
+				m = c.max
+			}
+			c.msize = m
+		} else if uint64(m)+ioHdrSize > uint64(c.msize) {
+			m = 0
+			if c.msize > ioHdrSize {
+				m = c.msize - ioHdrSize
+			}
+		}
+		binary.LittleEndian.PutUint32(h[at:], m)
+	}
+	c.hdr, c.left = h, size-uint32(len(h))
+	return nil
+}
@@ -0,0 +1,112 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"bytes"
+	"encoding/binary"
+	"io/ioutil"
+	"net"
+	"testing"
+	"testing/iotest"
+)
+
+// tmsg returns a 9p message of type typ with msize where
+// Tversion has it.
+func tmsg(typ byte, msize uint32) []byte {
+	b := make([]byte, 11)
+	binary.LittleEndian.PutUint32(b, 21)
+	b[4] = typ
+	binary.LittleEndian.PutUint16(b[5:], 0xffff)
+	binary.LittleEndian.PutUint32(b[7:], msize)
+	return append(b, "\x08\x009P2000.L"...)
+}
+
+// tio returns a Tread or Treaddir, typ, for count bytes.
+func tio(typ byte, count uint32) []byte {
+	b := make([]byte, 23)
+	binary.LittleEndian.PutUint32(b, 23)
+	b[4] = typ
+	binary.LittleEndian.PutUint16(b[5:], 1)
+	binary.LittleEndian.PutUint32(b[7:], 2)
+	binary.LittleEndian.PutUint64(b[11:], 4096)
+	binary.LittleEndian.PutUint32(b[19:], count)
+	return b
+}
+
+func cat(m ...[]byte) []byte {
+	return bytes.Join(m, nil)
+}
+
+func TestMsizeConnRead(t *testing.T) {
+	for _, tt := range []struct {
+		name string
+		in   []byte
+		want []byte
+		err  bool
+	}{
+		{name: "cut", in: tmsg(tversion, 8<<20), want: tmsg(tversion, 1<<20)},
+		{name: "max", in: tmsg(tversion, 1<<20), want: tmsg(tversion, 1<<20)},
+		{name: "smaller", in: tmsg(tversion, 8192), want: tmsg(tversion, 8192)},
+		{name: "not Tversion", in: tmsg(tversion+10, 8<<20), want: tmsg(tversion+10, 8<<20)},
+		{name: "no message", in: []byte{}, want: []byte{}},
+		{name: "short", in: tmsg(tversion, 8<<20)[:5], err: true},
+		{name: "cut in the middle", in: tmsg(tversion, 8<<20)[:15], err: true},
+		{name: "too small", in: []byte{3, 0, 0, 0, tversion, 0, 0}, err: true},
+		{
+			name: "second Tversion",
+			in:   cat(tmsg(tversion, 8192), tmsg(tversion, 8<<20)),
+			want: cat(tmsg(tversion, 8192), tmsg(tversion, 1<<20)),
+		},
+		{
+			name: "Tread",
+			in:   cat(tmsg(tversion, 8192), tio(tread, 8192-11), tio(tread, 4<<20), tio(tread, 100)),
+			want: cat(tmsg(tversion, 8192), tio(tread, 8192-11), tio(tread, 8192-11), tio(tread, 100)),
+		},
+		{
+			name: "Treaddir",
+			in:   cat(tmsg(tversion, 8192), tio(treaddir, 1<<20)),
+			want: cat(tmsg(tversion, 8192), tio(treaddir, 8192-11)),
+		},
+		{
+			name: "Tread before Tversion",
+			in:   tio(tread, 4<<20),
+			want: tio(tread, 1<<20-11),
+		},
+		{
+			name: "tiny msize",
+			in:   cat(tmsg(tversion, 4), tio(tread, 100)),
+			want: cat(tmsg(tversion, 4), tio(tread, 0)),
+		},
+		{
+			name: "other messages",
+			in:   cat(tmsg(tversion, 8192), tmsg(tversion+10, 8<<20), tio(tread, 10)),
+			want: cat(tmsg(tversion, 8192), tmsg(tversion+10, 8<<20), tio(tread, 10)),
+		},
+	} {
+		t.Run(tt.name, func(t *testing.T) {
+			r, w := net.Pipe()
+			defer r.Close()
+			go func() {
+				w.Write(tt.in)
+				w.Close()
+			}()
+			// One byte at a time, so the header is read in pieces.
+			got, err := ioutil.ReadAll(iotest.OneByteReader(&msizeConn{Conn: r, max: 1 << 20}))
+			if tt.err {
+				if err == nil {
+					t.Fatalf("Read: got nil, want error")
+				}
+				return
+			}
+			if err != nil {
+				t.Fatalf("Read: %v", err)
+			}
+			if !bytes.Equal(got, tt.want) {
+				t.Errorf("Read: got %q, want %q", got, tt.want)
+			}
+		})
+	}
+}
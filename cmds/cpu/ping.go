@@ -0,0 +1,43 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"log"
+	"time"
+
+	ossh "golang.org/x/crypto/ssh"
+)
+
+var pingEvery = flag.Duration("ping", 0, "how often the client checks, over ssh, that the server is still there; if it does not answer in that time, the client gives up (0 for never)")
+
+// ping sends an ssh keepalive request on cl every d, as ssh's
+// ServerAliveInterval does. The server need not know the request,
+// only answer it. If it does not answer within d, ping closes cl,
+// so that a dead connection ends the session instead of hanging.
+func ping(cl *ossh.Client, d time.Duration) {
+	t := time.NewTicker(d)
+	defer t.Stop()
+	for range t.C {
+		errc := make(chan error, 1)
+		go func() {
+			_, _, err := cl.SendRequest("keepalive@openssh.com", true, nil)
+			errc <- err
+		}()
+		select {
+		case err := <-errc:
+			if err != nil {
+				// The connection is gone already.
+				v("ping: %v", err)
+				return
+			}
+		case <-time.After(d):
+			log.Printf("Server has not answered for %v, giving up\r", d)
+			cl.Close()
+			return
+		}
+	}
+}
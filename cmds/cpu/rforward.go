@@ -0,0 +1,157 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"io"
+	"net"
+	"strconv"
+	"sync"
+
+	"github.com/gliderlabs/ssh"
+	ossh "golang.org/x/crypto/ssh"
+)
+
+// ninePorts holds the -port9p of the running sessions: the
+// forwarded ports their remotes mount the client's files from.
+var ninePorts = struct {
+	sync.Mutex
+	n map[string]int
+}{n: map[string]int{}}
+
+// nineP marks port as a session's 9P port, until the returned
+// func is called. An empty port is no port.
+func nineP(port string) func() {
+	ninePorts.Lock()
+	defer ninePorts.Unlock()
+	if port == "" {
+		return func() {}
+	}
+	ninePorts.n[port]++
+	return func() {
+		ninePorts.Lock()
+		defer ninePorts.Unlock()
+		if ninePorts.n[port]--; ninePorts.n[port] == 0 {
+			delete(ninePorts.n, port)
+		}
+	}
+}
+
+func isNineP(port string) bool {
+	ninePorts.Lock()
+	defer ninePorts.Unlock()
+	return ninePorts.n[port] > 0
+}
+
+// The payloads of RFC 4254 7.1 and 7.2.
+type (
+	rforwardRequest struct {
+		BindAddr string
+		BindPort uint32
+	}
+	rforwardSuccess struct {
+		BindPort uint32
+	}
+	rforwardChannel struct {
+		DestAddr   string
+		DestPort   uint32
+		OriginAddr string
+		OriginPort uint32
+	}
+)
+
+// rforward does what ssh.ForwardedTCPHandler does, listening
+// for the client's tcpip-forward requests, but it counts the bytes
+// of the connections to a session's 9P port in the metrics.
+type rforward struct {
+	sync.Mutex
+	forwards map[string]net.Listener
+}
+
+func (h *rforward) HandleSSHRequest(ctx ssh.Context, srv *ssh.Server, req *ossh.Request) (bool, []byte) {
+	var r rforwardRequest
+	if err := ossh.Unmarshal(req.Payload, &r); err != nil {
+		v("%v: %v", req.Type, err)
+		return false, nil
+	}
+	addr := net.JoinHostPort(r.BindAddr, strconv.Itoa(int(r.BindPort)))
+	switch req.Type {
+	case "tcpip-forward":
+		if srv.ReversePortForwardingCallback == nil || !srv.ReversePortForwardingCallback(ctx, r.BindAddr, r.BindPort) {
+			return false, []byte("port forwarding is disabled")
+		}
+		ln, err := net.Listen("tcp", addr)
+		if err != nil {
+			v("%v: %v", req.Type, err)
+			return false, nil
+		}
+		// Port 0 asks us to pick; the cancel names the one we did.
+		_, port, _ := net.SplitHostPort(ln.Addr().String())
+		p, _ := strconv.Atoi(port)
+		addr = net.JoinHostPort(r.BindAddr, port)
+		h.Lock()
+		if h.forwards == nil {
+			h.forwards = map[string]net.Listener{}
+		}
+		h.forwards[addr] = ln
+		h.Unlock()
+		go func() {
+			<-ctx.Done()
+			ln.Close()
+		}()
+		conn := ctx.Value(ssh.ContextKeyConn).(*ossh.ServerConn)
+		go h.serve(conn, ln, addr, rforwardChannel{DestAddr: r.BindAddr, DestPort: uint32(p)})
+		return true, ossh.Marshal(&rforwardSuccess{uint32(p)})
+	case "cancel-tcpip-forward":
+		h.Lock()
+		ln, ok := h.forwards[addr]
+		h.Unlock()
+		if ok {
+			ln.Close()
+		}
+		return true, nil
+	}
+	return false, nil
+}
+
+// serve opens a forwarded-tcpip channel on conn for each
+// connection to ln, and copies between them.
+func (h *rforward) serve(conn *ossh.ServerConn, ln net.Listener, addr string, ch rforwardChannel) {
+	defer func() {
+		h.Lock()
+		delete(h.forwards, addr)
+		h.Unlock()
+	}()
+	_, port, _ := net.SplitHostPort(ln.Addr().String())
+	for {
+		c, err := ln.Accept()
+		if err != nil {
+			v("forward %v: %v", addr, err)
+			return
+		}
+		if isNineP(port) {
+			c = &countConn{c}
+		}
+		host, p, _ := net.SplitHostPort(c.RemoteAddr().String())
+		o, _ := strconv.Atoi(p)
+		ch.OriginAddr, ch.OriginPort = host, uint32(o)
+		go func(c net.Conn, payload []byte) {
+			defer c.Close()
+			s, reqs, err := conn.OpenChannel("forwarded-tcpip", payload)
+			if err != nil {
+				v("forward %v: %v", addr, err)
+				return
+			}
+			defer s.Close()
+			go ossh.DiscardRequests(reqs)
+			go func() {
+				defer s.Close()
+				defer c.Close()
+				io.Copy(s, c)
+			}()
+			io.Copy(c, s)
+		}(c, ossh.Marshal(&ch))
+	}
+}
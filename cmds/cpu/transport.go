@@ -0,0 +1,512 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+// The cpu server can listen on several kinds of network.
+// Whatever the network, what comes out is a net.Listener,
+// so the ssh server and the session handler never need
+// to know which one they are on.
+package main
+
+import (
+	"errors"
+	"flag"
+	"fmt"
+	"log"
+	"net"
+	"os"
+	"os/user"
+	"path/filepath"
+	"strconv"
+	"strings"
+	"sync"
+	"syscall"
+	"time"
+
+	ossh "golang.org/x/crypto/ssh"
+	"golang.org/x/sys/unix"
+)
+
+var (
+	keepAlive  = flag.Duration("keepalive", 15*time.Second, "how long a server connection is idle before TCP keepalive probes start (0 to turn keepalives off)")
+	keepIntvl  = flag.Duration("keepaliveinterval", 0, "time between TCP keepalive probes on server connections (default -keepalive)")
+	keepCount  = flag.Int("keepalivecount", 0, "how many TCP keepalive probes go unanswered before a server connection is dropped (default the kernel's, net.ipv4.tcp_keepalive_probes)")
+	connect    = flag.String("connect", "", "address, on -network or as net!addr, the server dials to reach a client started with -reverse, instead of listening")
+	reverse    = flag.Bool("reverse", false, "wait for a server started with -connect to dial host, instead of dialing it; needs -hk")
+	backlog    = flag.Int("backlog", 0, "listen backlog for the server's sockets; the kernel caps it at net.core.somaxconn (default somaxconn)")
+	retries    = flag.Int("bindretries", 0, "how many more times the server tries to listen on an address before giving up")
+	retryDelay = flag.Duration("bindretrydelay", time.Second, "how long the server waits before its first retry of a listen; the wait doubles each time")
+	allowUID   = flag.String("allowuid", "", "comma separated users or uids the server takes unix socket connections from (default anyone who can connect)")
+)
+
+// listeners maps the networks that need special handling
+// to the function that listens on them. Anything else is
+// passed straight to net.Listen.
+var listeners = map[string]func(addr string) (net.Listener, error){
+	"unix":  unixListen,
+	"vsock": vsockListen,
+}
+
+// listen returns a Listener for the cpu server on network n.
+// The net package picks the socket family from the address and
+// always sets IPV6_V6ONLY on v6 sockets: off for tcp, so that
+// [::]:port takes both families, and on for tcp6.
+func listen(n, addr string) (net.Listener, error) {
+	var (
+		l   net.Listener
+		err error
+	)
+	if f, ok := listeners[n]; ok {
+		l, err = f(addr)
+	} else {
+		l, err = net.Listen(n, addr)
+	}
+	if err != nil || *backlog == 0 {
+		return l, err
+	}
+	if err := setBacklog(l, *backlog); err != nil {
+		l.Close()
+		return nil, err
+	}
+	return l, nil
+}
+
+// setBacklog sets the listen backlog of l to n. Go listens with
+// net.core.somaxconn, which is also the most the kernel allows;
+// asking for more gets somaxconn, silently. On Linux, calling
+// listen(2) again on a listening socket just changes the backlog.
+func setBacklog(l net.Listener, n int) error {
+	sc, ok := l.(syscall.Conn)
+	if !ok {
+		return fmt.Errorf("-backlog: can't set the backlog of a %T", l)
+	}
+	rc, err := sc.SyscallConn()
+	if err != nil {
+		return err
+	}
+	var lerr error
+	if err := rc.Control(func(fd uintptr) {
+		lerr = unix.Listen(int(fd), n)
+	}); err != nil {
+		return err
+	}
+	if lerr != nil {
+		return fmt.Errorf("-backlog %d: %v", n, lerr)
+	}
+	return nil
+}
+
+// activated returns the listening socket systemd passed us,
+// or nil if we were not socket activated. systemd's sockets
+// start at fd 3, and we only want the one.
+func activated() (net.Listener, error) {
+	if os.Getenv("LISTEN_PID") != strconv.Itoa(os.Getpid()) {
+		return nil, nil
+	}
+	fds := os.Getenv("LISTEN_FDS")
+	n, err := strconv.Atoi(fds)
+	if err != nil || n < 1 {
+		return nil, fmt.Errorf("LISTEN_FDS=%q: no socket to listen on", fds)
+	}
+	if n > 1 {
+		log.Printf("Warning: systemd passed %d sockets, using only the first", n)
+	}
+	// Our children must not think they were activated too.
+	for _, e := range []string{"LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"} {
+		os.Unsetenv(e)
+	}
+	f := os.NewFile(3, "LISTEN_FDS")
+	defer f.Close()
+	return net.FileListener(f)
+}
+
+// listenAll returns the listeners the server serves on: those
+// -daemonize handed us, the one systemd passed us, or one for each
+// address in -addr. Addresses
+// are on -network unless they name their own, Plan 9 style, as in
+// unix!/run/cpu.sock,tcp!127.0.0.1:2222.
+func listenAll() ([]net.Listener, error) {
+	if ls, err := inherited(); ls != nil || err != nil {
+		return ls, err
+	}
+	l, err := activated()
+	if err != nil {
+		return nil, err
+	}
+	if l != nil {
+		return []net.Listener{l}, nil
+	}
+	if *connect != "" {
+		n, a := *network, *connect
+		if i := strings.Index(a, "!"); i >= 0 {
+			n, a = a[:i], a[i+1:]
+		}
+		return []net.Listener{newDialListener(n, a)}, nil
+	}
+	var ls []net.Listener
+	for _, a := range strings.Split(*addr, ",") {
+		n := *network
+		if i := strings.Index(a, "!"); i >= 0 {
+			n, a = a[:i], a[i+1:]
+		}
+		if a == "" {
+			a = defaultAddr(n)
+		}
+		l, err := listenRetry(n, a)
+		if err != nil {
+			for _, l := range ls {
+				l.Close()
+			}
+			return nil, fmt.Errorf("can't listen on %v %v: %v", n, a, err)
+		}
+		ls = append(ls, l)
+	}
+	return ls, nil
+}
+
+// listenRetry listens on a on network n, trying -bindretries more
+// times if need be. A server restarted after a crash may find its
+// address still taken for a while: tcp by the old socket, a unix
+// socket by a server that is still going away, vsock by a device
+// that is not up yet. Nothing says which errors will pass, so
+// any error gets retried.
+func listenRetry(n, a string) (net.Listener, error) {
+	d := *retryDelay
+	for i := 0; ; i++ {
+		l, err := listen(n, a)
+		if err == nil || i >= *retries {
+			return l, err
+		}
+		log.Printf("Warning: listen on %v %v: %v; trying again in %v", n, a, err, d)
+		time.Sleep(d)
+		d *= 2
+	}
+}
+
+// defaultAddr returns the address to listen on for network n
+// when none is given. vsock addresses are cid:port, and an empty
+// cid is VMADDR_CID_ANY, so ":port" serves for vsock as well as tcp.
+func defaultAddr(n string) string {
+	if n == "unix" {
+		return "/tmp/cpu.sock"
+	}
+	return ":" + *port
+}
+
+// unixListen listens on a unix socket at path. A dead socket left
+// over from an earlier run is removed; anything else at path is
+// left alone, since it might be somebody's data. The socket is
+// created mode 0600. The net package unlinks it again on Close.
+//
+// A path starting with @ (or NUL) is in the abstract namespace:
+// there is no file to clean up, but no permissions either, so
+// anyone in our network namespace can connect.
+func unixListen(path string) (net.Listener, error) {
+	if strings.HasPrefix(path, "@") || strings.HasPrefix(path, "\x00") {
+		return net.Listen("unix", "@"+path[1:])
+	}
+	if fi, err := os.Lstat(path); err == nil {
+		if fi.Mode()&os.ModeSocket == 0 {
+			return nil, fmt.Errorf("%v exists and is not a socket; not removing it", path)
+		}
+		if c, err := net.Dial("unix", path); err == nil {
+			c.Close()
+			return nil, fmt.Errorf("%v is in use by another server", path)
+		}
+		v("removing stale socket %v", path)
+		if err := os.Remove(path); err != nil {
+			return nil, err
+		}
+	}
+	if _, err := os.Stat(filepath.Dir(path)); err != nil {
+		return nil, fmt.Errorf("unix socket %v: %v", path, err)
+	}
+	// Set the umask rather than chmod after the fact, so there is
+	// no window in which other users can connect.
+	mask := unix.Umask(0177)
+	l, err := net.Listen("unix", path)
+	unix.Umask(mask)
+	return l, err
+}
+
+// accepted is the server's ConnCallback, called for each
+// connection before ssh sees it.
+func accepted(c net.Conn) net.Conn {
+	connections.Add(c.LocalAddr().Network(), 1)
+	c = withPeerCred(c)
+	if !uidAllowed(c) {
+		log.Printf("Refusing connection from %v: not in -allowuid", c.RemoteAddr())
+		droppedConns.Add(1)
+		return nil
+	}
+	// Dropping is cheap; logging every one is not.
+	if p := peer(c); !rateOK(p, time.Now()) {
+		droppedConns.Add(1)
+		v("dropping connection from %v: over -maxconnsperminute", p)
+		return nil
+	}
+	return tune(c)
+}
+
+// peer names the other end of c: the IP address for tcp, the
+// CID for vsock, and the uid for unix sockets, where the
+// address says nothing.
+func peer(c net.Conn) string {
+	switch a := c.RemoteAddr().(type) {
+	case *net.TCPAddr:
+		return a.IP.String()
+	case *vsockAddr:
+		return fmt.Sprintf("cid %d", a.cid)
+	case *unixPeer:
+		return fmt.Sprintf("uid %d", a.cred.Uid)
+	}
+	return c.RemoteAddr().String()
+}
+
+// unixPeer is the RemoteAddr of a unix socket connection: the
+// address is usually empty, so we say who is calling instead.
+// It is what the session handler and the audit log see.
+type unixPeer struct {
+	net.Addr
+	cred *unix.Ucred
+}
+
+func (a *unixPeer) String() string {
+	return fmt.Sprintf("uid %d gid %d pid %d", a.cred.Uid, a.cred.Gid, a.cred.Pid)
+}
+
+// peerConn is a connection with a better RemoteAddr.
+type peerConn struct {
+	net.Conn
+	remote net.Addr
+}
+
+func (c *peerConn) RemoteAddr() net.Addr {
+	return c.remote
+}
+
+// withPeerCred returns c with its peer's credentials in its
+// RemoteAddr, if it is a unix socket.
+func withPeerCred(c net.Conn) net.Conn {
+	uc, ok := c.(*net.UnixConn)
+	if !ok {
+		return c
+	}
+	cred, err := peerCred(uc)
+	if err != nil {
+		v("no peer credentials for %v: %v", c.RemoteAddr(), err)
+		return c
+	}
+	return &peerConn{Conn: c, remote: &unixPeer{Addr: c.RemoteAddr(), cred: cred}}
+}
+
+// uidAllowed reports whether -allowuid lets the peer of c in.
+// Only unix sockets tell us who is calling, so the rest are
+// left to the ssh keys.
+func uidAllowed(c net.Conn) bool {
+	if *allowUID == "" || c.LocalAddr().Network() != "unix" {
+		return true
+	}
+	p, ok := c.RemoteAddr().(*unixPeer)
+	if !ok {
+		return false
+	}
+	uid := strconv.FormatUint(uint64(p.cred.Uid), 10)
+	for _, a := range strings.Split(*allowUID, ",") {
+		if a == uid {
+			return true
+		}
+		if u, err := user.Lookup(a); err == nil && u.Uid == uid {
+			return true
+		}
+	}
+	return false
+}
+
+// peerCred returns the credentials of the process at the other
+// end of c, as they were when it connected.
+func peerCred(c *net.UnixConn) (*unix.Ucred, error) {
+	rc, err := c.SyscallConn()
+	if err != nil {
+		return nil, err
+	}
+	var (
+		cred *unix.Ucred
+		cerr error
+	)
+	if err := rc.Control(func(fd uintptr) {
+		cred, cerr = unix.GetsockoptUcred(int(fd), unix.SOL_SOCKET, unix.SO_PEERCRED)
+	}); err != nil {
+		return nil, err
+	}
+	return cred, cerr
+}
+
+// tune sets the socket options for a connection the server
+// accepted. Go already sets SO_REUSEADDR on the listener and
+// TCP_NODELAY on connections; we say so anyway, since Nagle
+// is death to interactive sessions.
+func tune(c net.Conn) net.Conn {
+	tc, ok := c.(*net.TCPConn)
+	if !ok {
+		return c
+	}
+	if err := tc.SetNoDelay(true); err != nil {
+		v("tune: SetNoDelay: %v", err)
+	}
+	if err := tc.SetKeepAlive(*keepAlive > 0); err != nil {
+		v("tune: SetKeepAlive: %v", err)
+	}
+	if *keepAlive > 0 {
+		if err := tc.SetKeepAlivePeriod(*keepAlive); err != nil {
+			v("tune: SetKeepAlivePeriod: %v", err)
+		}
+		if err := keepAliveProbes(tc, *keepIntvl, *keepCount); err != nil {
+			v("tune: keepAliveProbes: %v", err)
+		}
+	}
+	return c
+}
+
+// keepAliveProbes sets the time between keepalive probes on tc,
+// and how many go unanswered before it is dropped. Go has only
+// the one period, which it uses for the idle time and the time
+// between probes. Zero leaves a setting as it is.
+func keepAliveProbes(tc *net.TCPConn, intvl time.Duration, count int) error {
+	rc, err := tc.SyscallConn()
+	if err != nil {
+		return err
+	}
+	// Round up, as SetKeepAlivePeriod does; the kernel wants seconds.
+	secs := int((intvl + time.Second - 1) / time.Second)
+	var serr error
+	if err := rc.Control(func(fd uintptr) {
+		if intvl > 0 {
+			if serr = unix.SetsockoptInt(int(fd), unix.IPPROTO_TCP, unix.TCP_KEEPINTVL, secs); serr != nil {
+				serr = fmt.Errorf("TCP_KEEPINTVL: %v", serr)
+				return
+			}
+		}
+		if count > 0 {
+			if serr = unix.SetsockoptInt(int(fd), unix.IPPROTO_TCP, unix.TCP_KEEPCNT, count); serr != nil {
+				serr = fmt.Errorf("TCP_KEEPCNT: %v", serr)
+			}
+		}
+	}); err != nil {
+		return err
+	}
+	return serr
+}
+
+var errClosed = errors.New("listener closed")
+
+// dialListener is a Listener whose Accept dials out, for servers
+// that nobody can reach but that can reach their clients. It has
+// one connection at a time: Accept waits until the last one is
+// closed, then dials again. Dials are at least a second apart,
+// whether the last one failed or its connection did, so a peer
+// that hangs up at once does not have us spin.
+type dialListener struct {
+	network, addr string
+	idle          chan struct{} // holds a token while no connection is open
+	done          chan struct{}
+	once          sync.Once
+	last          time.Time // of the last dial
+}
+
+func newDialListener(n, a string) *dialListener {
+	l := &dialListener{network: n, addr: a, idle: make(chan struct{}, 1), done: make(chan struct{})}
+	l.idle <- struct{}{}
+	return l
+}
+
+// Accept implements net.Listener.Accept.
+func (l *dialListener) Accept() (net.Conn, error) {
+	select {
+	case <-l.idle:
+	case <-l.done:
+		return nil, errClosed
+	}
+	for {
+		select {
+		case <-time.After(time.Until(l.last.Add(time.Second))):
+		case <-l.done:
+			l.idle <- struct{}{}
+			return nil, errClosed
+		}
+		l.last = time.Now()
+		c, err := net.Dial(l.network, l.addr)
+		if err == nil {
+			return &dialConn{Conn: c, l: l}, nil
+		}
+		v("dial %v %v: %v", l.network, l.addr, err)
+	}
+}
+
+// Close implements net.Listener.Close.
+func (l *dialListener) Close() error {
+	l.once.Do(func() {
+		close(l.done)
+	})
+	return nil
+}
+
+// Addr implements net.Listener.Addr.
+func (l *dialListener) Addr() net.Addr {
+	return dialAddr{l.network, l.addr}
+}
+
+// dialAddr is the address a dialListener dials.
+type dialAddr struct {
+	network, addr string
+}
+
+func (a dialAddr) Network() string {
+	return a.network
+}
+
+func (a dialAddr) String() string {
+	return "dialing " + a.addr
+}
+
+// dialConn is a connection made by a dialListener. Closing it
+// lets the listener dial again.
+type dialConn struct {
+	net.Conn
+	l    *dialListener
+	once sync.Once
+}
+
+func (c *dialConn) Close() error {
+	err := c.Conn.Close()
+	c.once.Do(func() {
+		c.l.idle <- struct{}{}
+	})
+	return err
+}
+
+// reverseDial waits for a cpu server started with -connect to
+// call us at a, then talks ssh to it as if we had dialed it.
+// Whoever calls first gets our files, so it must have the host
+// key in -hk; config checks it.
+func reverseDial(n, a string, config *ossh.ClientConfig) (*ossh.Client, error) {
+	if *hostKeyFile == "" {
+		return nil, fmt.Errorf("-reverse needs -hk, the server's host key: anyone could call us")
+	}
+	l, err := net.Listen(n, a)
+	if err != nil {
+		return nil, err
+	}
+	defer l.Close()
+	v("waiting for a server to connect to %v", l.Addr())
+	c, err := l.Accept()
+	if err != nil {
+		return nil, err
+	}
+	conn, chans, reqs, err := ossh.NewClientConn(c, a, config)
+	if err != nil {
+		c.Close()
+		return nil, err
+	}
+	return ossh.NewClient(conn, chans, reqs), nil
+}
@@ -0,0 +1,142 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io"
+	"net"
+	"strings"
+
+	ossh "golang.org/x/crypto/ssh"
+)
+
+// tunnels is a repeatable flag of [bindaddr:]port:host:hostport
+// port forwards, in the form ssh -L and -R take.
+type tunnels []string
+
+func (t *tunnels) String() string {
+	return strings.Join(*t, ",")
+}
+
+func (t *tunnels) Set(s string) error {
+	if _, _, err := parseTunnel(s); err != nil {
+		return err
+	}
+	*t = append(*t, s)
+	return nil
+}
+
+func tunnelFlag(name, usage string) *tunnels {
+	t := &tunnels{}
+	flag.Var(t, name, usage)
+	return t
+}
+
+var (
+	localTunnels  = tunnelFlag("L", "[bindaddr:]port:host:hostport: forward connections to port here to host:hostport as seen from the remote; may be repeated")
+	remoteTunnels = tunnelFlag("R", "[bindaddr:]port:host:hostport: forward connections to port on the remote to host:hostport as seen from here; may be repeated")
+)
+
+// parseTunnel returns the address to listen on and the address
+// to connect to for t. As with ssh, bindaddr defaults to localhost,
+// and v6 addresses go in brackets, as in [::1]:8080:[::1]:80.
+func parseTunnel(t string) (string, string, error) {
+	f, err := splitTunnel(t)
+	if err != nil {
+		return "", "", err
+	}
+	switch len(f) {
+	case 3:
+		return net.JoinHostPort("localhost", f[0]), net.JoinHostPort(f[1], f[2]), nil
+	case 4:
+		return net.JoinHostPort(f[0], f[1]), net.JoinHostPort(f[2], f[3]), nil
+	}
+	return "", "", fmt.Errorf("%q is not [bindaddr:]port:host:hostport", t)
+}
+
+// splitTunnel splits t at the colons that are not in brackets,
+// and takes the brackets off.
+func splitTunnel(t string) ([]string, error) {
+	var f []string
+	for t != "" {
+		if t[0] == '[' {
+			i := strings.Index(t, "]")
+			if i < 0 {
+				return nil, fmt.Errorf("%q: missing ]", t)
+			}
+			f = append(f, t[1:i])
+			t = t[i+1:]
+			if t != "" && t[0] != ':' {
+				return nil, fmt.Errorf("%q: want : after ]", t)
+			}
+		} else {
+			i := strings.Index(t, ":")
+			if i < 0 {
+				i = len(t)
+			}
+			f = append(f, t[:i])
+			t = t[i:]
+		}
+		if t != "" {
+			t = t[1:]
+			// A trailing colon leaves an empty field.
+			if t == "" {
+				f = append(f, "")
+			}
+		}
+	}
+	return f, nil
+}
+
+// startTunnels sets up the -L and -R forwards over cl. They
+// run until cl is closed.
+func startTunnels(cl *ossh.Client) error {
+	for _, t := range *localTunnels {
+		l, to, _ := parseTunnel(t)
+		ln, err := net.Listen("tcp", l)
+		if err != nil {
+			return fmt.Errorf("-L %v: %v", t, err)
+		}
+		go tunnel(ln, func() (net.Conn, error) {
+			return cl.Dial("tcp", to)
+		})
+	}
+	for _, t := range *remoteTunnels {
+		l, to, _ := parseTunnel(t)
+		ln, err := cl.Listen("tcp", l)
+		if err != nil {
+			return fmt.Errorf("-R %v: %v", t, err)
+		}
+		go tunnel(ln, func() (net.Conn, error) {
+			return net.Dial("tcp", to)
+		})
+	}
+	return nil
+}
+
+// tunnel accepts connections on l until it is closed, and
+// copies each to and from a connection made by dial.
+func tunnel(l net.Listener, dial func() (net.Conn, error)) {
+	for {
+		c, err := l.Accept()
+		if err != nil {
+			v("tunnel %v: %v", l.Addr(), err)
+			return
+		}
+		go func() {
+			defer c.Close()
+			t, err := dial()
+			if err != nil {
+				v("tunnel %v: %v", l.Addr(), err)
+				return
+			}
+			defer t.Close()
+			go io.Copy(t, c)
+			io.Copy(c, t)
+		}()
+	}
+}
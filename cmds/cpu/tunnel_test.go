@@ -0,0 +1,43 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import "testing"
+
+func TestParseTunnel(t *testing.T) {
+	for _, tt := range []struct {
+		t, listen, to string
+		err           bool
+	}{
+		{t: "8080:web:80", listen: "localhost:8080", to: "web:80"},
+		{t: "0.0.0.0:8080:web:80", listen: "0.0.0.0:8080", to: "web:80"},
+		{t: ":8080:web:80", listen: ":8080", to: "web:80"},
+		{t: "8080:[::1]:80", listen: "localhost:8080", to: "[::1]:80"},
+		{t: "[::1]:8080:[::1]:80", listen: "[::1]:8080", to: "[::1]:80"},
+		{t: "[fe80::1%eth0]:8080:web:80", listen: "[fe80::1%eth0]:8080", to: "web:80"},
+		{t: "8080:web", err: true},
+		{t: "8080", err: true},
+		{t: "", err: true},
+		{t: "1:2:3:4:5", err: true},
+		{t: "::1:8080:web:80", err: true},
+		{t: "[::1:8080:web:80", err: true},
+		{t: "[::1]8080:web:80", err: true},
+	} {
+		l, to, err := parseTunnel(tt.t)
+		if tt.err {
+			if err == nil {
+				t.Errorf("parseTunnel(%q): got %q, %q, want error", tt.t, l, to)
+			}
+			continue
+		}
+		if err != nil {
+			t.Errorf("parseTunnel(%q): %v", tt.t, err)
+			continue
+		}
+		if l != tt.listen || to != tt.to {
+			t.Errorf("parseTunnel(%q): got %q, %q, want %q, %q", tt.t, l, to, tt.listen, tt.to)
+		}
+	}
+}
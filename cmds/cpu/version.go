@@ -0,0 +1,41 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"fmt"
+	"strings"
+)
+
+// protocolVersion is the version of what the cpu client and
+// server expect of each other, e.g. the -remote command line.
+// Bump the major number for changes that break old peers. New
+// -remote flags that are only sent when asked for, as remoteFlags
+// does, don't.
+const protocolVersion = "1.0"
+
+// versionString goes in the ssh version banner of both sides,
+// which is exchanged before anything else.
+const versionString = "cpu-" + protocolVersion
+
+// checkVersion returns an error if banner, the ssh version banner
+// of the other side, is from a cpu whose major protocol version
+// is not ours. Other banners are fine: plenty of sshds can start
+// cpu -remote, and old cpus did not say who they were.
+func checkVersion(banner string) error {
+	i := strings.Index(banner, "-cpu-")
+	if i < 0 {
+		return nil
+	}
+	f := strings.Fields(banner[i+len("-cpu-"):])
+	if len(f) == 0 || major(f[0]) != major(protocolVersion) {
+		return fmt.Errorf("incompatible version: we speak cpu protocol %v, the other side is %q", protocolVersion, banner)
+	}
+	return nil
+}
+
+func major(v string) string {
+	return strings.SplitN(v, ".", 2)[0]
+}
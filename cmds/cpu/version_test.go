@@ -0,0 +1,42 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import "testing"
+
+func TestCheckVersion(t *testing.T) {
+	for _, tt := range []struct {
+		banner string
+		err    bool
+	}{
+		{banner: "SSH-2.0-" + versionString},
+		{banner: "SSH-2.0-cpu-1.7"},
+		{banner: "SSH-2.0-cpu-1.0 with a comment"},
+		{banner: "SSH-2.0-OpenSSH_7.9p1 Debian-10"},
+		{banner: "SSH-2.0-Go"},
+		{banner: "SSH-2.0-cpu-2.0", err: true},
+		{banner: "SSH-2.0-cpu-0.9", err: true},
+		{banner: "SSH-2.0-cpu-", err: true},
+	} {
+		if err := checkVersion(tt.banner); (err != nil) != tt.err {
+			t.Errorf("checkVersion(%q): got %v, want error %v", tt.banner, err, tt.err)
+		}
+	}
+}
+
+func TestMajor(t *testing.T) {
+	for _, tt := range []struct {
+		v, want string
+	}{
+		{"1.0", "1"},
+		{"1.2.3", "1"},
+		{"10", "10"},
+		{"", ""},
+	} {
+		if got := major(tt.v); got != tt.want {
+			t.Errorf("major(%q): got %q, want %q", tt.v, got, tt.want)
+		}
+	}
+}
@@ -0,0 +1,191 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"fmt"
+	"net"
+	"os"
+	"strconv"
+	"strings"
+	"syscall"
+
+	"golang.org/x/sys/unix"
+)
+
+// The net package knows nothing of AF_VSOCK, so we build
+// our own Listener from raw sockets. It is just enough to
+// let the ssh server Serve on it as it does on tcp.
+
+// vsockAddr is a net.Addr for AF_VSOCK.
+type vsockAddr struct {
+	cid, port uint32
+}
+
+func (a *vsockAddr) Network() string {
+	return "vsock"
+}
+
+func (a *vsockAddr) String() string {
+	return fmt.Sprintf("%d:%d", a.cid, a.port)
+}
+
+// parseVsockAddr parses a cid:port address. An empty cid,
+// or "any", means VMADDR_CID_ANY, so the usual ":port" works.
+func parseVsockAddr(addr string) (*vsockAddr, error) {
+	i := strings.LastIndex(addr, ":")
+	if i < 0 {
+		return nil, fmt.Errorf("vsock address %q: missing port", addr)
+	}
+	c, p := addr[:i], addr[i+1:]
+	a := &vsockAddr{cid: unix.VMADDR_CID_ANY}
+	if c != "" && c != "any" {
+		cid, err := strconv.ParseUint(c, 0, 32)
+		if err != nil {
+			return nil, fmt.Errorf("vsock address %q: bad cid: %v", addr, err)
+		}
+		a.cid = uint32(cid)
+	}
+	port, err := strconv.ParseUint(p, 0, 32)
+	if err != nil {
+		return nil, fmt.Errorf("vsock address %q: bad port: %v", addr, err)
+	}
+	a.port = uint32(port)
+	return a, nil
+}
+
+// vsockConn is an accepted vsock connection. The os.File
+// supplies Read, Write, Close and the deadlines.
+type vsockConn struct {
+	*os.File
+	local, remote net.Addr
+}
+
+func (c *vsockConn) LocalAddr() net.Addr {
+	return c.local
+}
+
+func (c *vsockConn) RemoteAddr() net.Addr {
+	return c.remote
+}
+
+// vsockListener implements net.Listener for AF_VSOCK.
+type vsockListener struct {
+	f    *os.File
+	addr *vsockAddr
+}
+
+func vsockListen(addr string) (net.Listener, error) {
+	a, err := parseVsockAddr(addr)
+	if err != nil {
+		return nil, err
+	}
+	fd, err := unix.Socket(unix.AF_VSOCK, unix.SOCK_STREAM|unix.SOCK_NONBLOCK|unix.SOCK_CLOEXEC, 0)
+	if err != nil {
+		return nil, fmt.Errorf("vsock socket: %v (is the vsock module loaded?)", err)
+	}
+	if err := unix.Bind(fd, &unix.SockaddrVM{CID: a.cid, Port: a.port}); err != nil {
+		unix.Close(fd)
+		return nil, fmt.Errorf("vsock bind %v: %v", a, err)
+	}
+	if err := unix.Listen(fd, unix.SOMAXCONN); err != nil {
+		unix.Close(fd)
+		return nil, fmt.Errorf("vsock listen %v: %v", a, err)
+	}
+	// The fd is non-blocking, so os.NewFile puts it in the
+	// runtime poller and Close will wake up a blocked Accept.
+	return &vsockListener{f: os.NewFile(uintptr(fd), "vsock:"+a.String()), addr: a}, nil
+}
+
+// Accept implements net.Listener.Accept.
+func (l *vsockListener) Accept() (net.Conn, error) {
+	rc, err := l.f.SyscallConn()
+	if err != nil {
+		return nil, err
+	}
+	var (
+		nfd  int
+		sa   unix.Sockaddr
+		aerr error
+	)
+	if err := rc.Read(func(fd uintptr) bool {
+		nfd, sa, aerr = unix.Accept4(int(fd), unix.SOCK_NONBLOCK|unix.SOCK_CLOEXEC)
+		return aerr != unix.EAGAIN
+	}); err != nil {
+		return nil, err
+	}
+	if aerr != nil {
+		return nil, aerr
+	}
+	r := &vsockAddr{}
+	if vm, ok := sa.(*unix.SockaddrVM); ok {
+		r.cid, r.port = vm.CID, vm.Port
+	}
+	v("vsock: accepted from %v", r)
+	return &vsockConn{File: os.NewFile(uintptr(nfd), "vsock:"+r.String()), local: l.addr, remote: r}, nil
+}
+
+// Close implements net.Listener.Close.
+func (l *vsockListener) Close() error {
+	return l.f.Close()
+}
+
+// Addr implements net.Listener.Addr.
+func (l *vsockListener) Addr() net.Addr {
+	return l.addr
+}
+
+// File returns a copy of l's socket, for -daemonize. We dup it
+// ourselves, as Fd would make l's socket blocking.
+func (l *vsockListener) File() (*os.File, error) {
+	rc, err := l.f.SyscallConn()
+	if err != nil {
+		return nil, err
+	}
+	var (
+		nfd  int
+		derr error
+	)
+	if err := rc.Control(func(fd uintptr) {
+		nfd, derr = unix.FcntlInt(fd, unix.F_DUPFD_CLOEXEC, 0)
+	}); err != nil {
+		return nil, err
+	}
+	if derr != nil {
+		return nil, derr
+	}
+	return os.NewFile(uintptr(nfd), l.f.Name()), nil
+}
+
+// vsockFileListener makes a listener of the vsock socket in f,
+// which -daemonize handed us. f is left open.
+func vsockFileListener(f *os.File) (net.Listener, error) {
+	nfd, err := unix.FcntlInt(f.Fd(), unix.F_DUPFD_CLOEXEC, 0)
+	if err != nil {
+		return nil, err
+	}
+	sa, err := unix.Getsockname(nfd)
+	if err != nil {
+		unix.Close(nfd)
+		return nil, err
+	}
+	vm, ok := sa.(*unix.SockaddrVM)
+	if !ok {
+		unix.Close(nfd)
+		return nil, fmt.Errorf("%v is not a vsock socket", f.Name())
+	}
+	// Passing it to us made it blocking; see vsockListen.
+	if err := unix.SetNonblock(nfd, true); err != nil {
+		unix.Close(nfd)
+		return nil, err
+	}
+	a := &vsockAddr{cid: vm.CID, port: vm.Port}
+	return &vsockListener{f: os.NewFile(uintptr(nfd), "vsock:"+a.String()), addr: a}, nil
+}
+
+// SyscallConn implements syscall.Conn, for -backlog.
+func (l *vsockListener) SyscallConn() (syscall.RawConn, error) {
+	return l.f.SyscallConn()
+}
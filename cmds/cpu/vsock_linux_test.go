@@ -0,0 +1,44 @@
+// Copyright 2018-2019 the u-root Authors. All rights reserved
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+package main
+
+import (
+	"testing"
+
+	"golang.org/x/sys/unix"
+)
+
+func TestParseVsockAddr(t *testing.T) {
+	for _, tt := range []struct {
+		addr string
+		want vsockAddr
+		err  bool
+	}{
+		{addr: "3:5000", want: vsockAddr{cid: 3, port: 5000}},
+		{addr: ":22", want: vsockAddr{cid: unix.VMADDR_CID_ANY, port: 22}},
+		{addr: "any:22", want: vsockAddr{cid: unix.VMADDR_CID_ANY, port: 22}},
+		{addr: "0x2:0x10", want: vsockAddr{cid: 2, port: 16}},
+		{addr: "5000", err: true},
+		{addr: "x:22", err: true},
+		{addr: "3:", err: true},
+		{addr: "3:x", err: true},
+		{addr: "3:4294967296", err: true},
+	} {
+		a, err := parseVsockAddr(tt.addr)
+		if tt.err {
+			if err == nil {
+				t.Errorf("parseVsockAddr(%q): got %+v, want error", tt.addr, a)
+			}
+			continue
+		}
+		if err != nil {
+			t.Errorf("parseVsockAddr(%q): %v", tt.addr, err)
+			continue
+		}
+		if *a != tt.want {
+			t.Errorf("parseVsockAddr(%q): got %+v, want %+v", tt.addr, *a, tt.want)
+		}
+	}
+}